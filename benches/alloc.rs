@@ -0,0 +1,62 @@
+//! Compares [`genalloc::Span`] against [`genalloc::typed::TypedSpan`] on a tight alloc/read loop,
+//! single against bulk [`genalloc::Span`] allocations, and [`genalloc::Ptr`] against
+//! [`genalloc::CopyPtr`] accesses.
+//!
+//! Run with `cargo bench`.
+
+use std::{hint::black_box, time::Instant};
+
+use genalloc::{typed::TypedSpan, Span};
+
+const ITERATIONS: usize = 100;
+const ALLOCS: usize = 10_000;
+
+fn bench(name: &str, mut f: impl FnMut()) {
+    f();
+    let start = Instant::now();
+    for _ in 0..ITERATIONS {
+        f();
+    }
+    println!("{name}: {:?}/iter", start.elapsed() / ITERATIONS as u32);
+}
+
+fn main() {
+    bench("Span alloc+read", || {
+        let mut span = Span::new();
+        let ptrs: Vec<_> = (0..ALLOCS).map(|i| span.alloc(i)).collect();
+        let sum: usize = ptrs.iter().map(|ptr| *ptr.read()).sum();
+        black_box(sum);
+    });
+    bench("TypedSpan alloc+read", || {
+        let mut span = TypedSpan::new();
+        let ptrs: Vec<_> = (0..ALLOCS).map(|i| span.alloc(i)).collect();
+        let sum: usize = ptrs.iter().map(|ptr| *ptr.read()).sum();
+        black_box(sum);
+    });
+    bench("Span alloc", || {
+        let mut span = Span::new();
+        let ptrs: Vec<_> = (0..ALLOCS).map(|i| span.alloc(i)).collect();
+        black_box(ptrs);
+    });
+    bench("Span alloc_iter", || {
+        let mut span = Span::new();
+        let ptrs = span.alloc_iter(0..ALLOCS);
+        black_box(ptrs);
+    });
+
+    let mut span = Span::new();
+    let ptr = span.alloc(0.0f32);
+    let copy_ptr = span.alloc_copy(0.0f32);
+    bench("Ptr<f32> read+write", || {
+        for _ in 0..ALLOCS {
+            let value = *black_box(ptr).read();
+            *ptr.write() = value + 1.0;
+        }
+    });
+    bench("CopyPtr<f32> get+set", || {
+        for _ in 0..ALLOCS {
+            let value = black_box(copy_ptr).get();
+            copy_ptr.set(value + 1.0);
+        }
+    });
+}
@@ -0,0 +1,73 @@
+//! Counts heap allocations made by a burst of fresh [`genalloc::Span`] allocations, and by
+//! allocations reusing recycled ones of the same type, for values stored inline and values too
+//! large for that.
+//!
+//! Run with `cargo bench`.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Instant,
+};
+
+use genalloc::Span;
+
+const ALLOCS: usize = 10_000;
+
+struct CountingAlloc;
+
+static COUNT: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        COUNT.fetch_add(1, Ordering::Relaxed);
+        unsafe { System.alloc(layout) }
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        unsafe { System.dealloc(ptr, layout) }
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+/// Runs `f` and prints the heap allocations it made.
+fn measure(name: &str, f: impl FnOnce()) {
+    let start = Instant::now();
+    let count = COUNT.load(Ordering::Relaxed);
+    f();
+    let count = COUNT.load(Ordering::Relaxed) - count;
+    println!(
+        "{ALLOCS} {name}: {count} heap allocations in {:?}",
+        start.elapsed()
+    );
+}
+
+/// Allocates fresh values made by `value`, then values reusing the recycled allocations.
+fn bench<T: 'static>(kind: &str, value: fn(usize) -> T) {
+    // A fresh thread starts with an empty recycled pool.
+    std::thread::scope(|scope| {
+        scope.spawn(|| {
+            let mut span = Span::with_capacity(ALLOCS);
+            measure(&format!("fresh {kind} allocs"), || {
+                for i in 0..ALLOCS {
+                    let _ = span.alloc(value(i));
+                }
+            });
+            // Recycled allocations reuse the heap allocations of values of the same type.
+            measure(&format!("same-type recycled {kind} allocs"), || {
+                for i in 0..ALLOCS {
+                    let _ = span.alloc(value(i));
+                    span.clear();
+                }
+            });
+        });
+    });
+}
+
+fn main() {
+    bench("inline", |i| i);
+    // 32 bytes don't fit inline, so these values are boxed.
+    bench("boxed", |i| [i as u64; 4]);
+}
@@ -0,0 +1,163 @@
+//! Generational allocations of small [`Copy`] values, see [`Span::alloc_copy`].
+//!
+//! Values are stored inline in [`Cell`]s, so accessing them is just a generation check and a
+//! copy, without borrow guards, heap indirections or downcasts.
+//! Allocations are recycled through a per-thread pool shared by all types.
+
+use std::{
+    cell::{Cell, RefCell},
+    marker::PhantomData,
+    mem::{align_of, size_of, MaybeUninit},
+};
+
+#[cfg(doc)]
+use crate::Span;
+use crate::{AccessError, DanglingError, RETIRED_GEN};
+
+/// Generational pointer to a small [`Copy`] value, see [`Span::alloc_copy`].
+///
+/// Goes stale when its [`Span`] is cleared or dropped, just like a [`Ptr`](crate::Ptr).
+pub struct CopyPtr<T> {
+    alloc: CopyAlloc,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Clone for CopyPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for CopyPtr<T> {}
+
+impl<T: Copy + 'static> CopyPtr<T> {
+    /// Stores `v` in a recycled or freshly leaked slot.
+    pub(crate) fn new(v: T) -> Self {
+        const {
+            assert!(
+                size_of::<T>() <= size_of::<Storage>() && align_of::<T>() <= align_of::<Storage>(),
+                "value is too large to be stored inline"
+            );
+        }
+        let slot = RECYCLED_COPY_ALLOCS
+            .with(|recycled| recycled.borrow_mut().pop())
+            .unwrap_or_else(CopySlot::leak);
+        // SAFETY: `T` fits into `Storage`, see the assertion above.
+        unsafe { slot.value.as_ptr().cast::<T>().write(v) };
+        Self {
+            alloc: CopyAlloc {
+                slot,
+                gen: slot.gen.get(),
+            },
+            _marker: PhantomData,
+        }
+    }
+
+    pub(crate) fn alloc(&self) -> CopyAlloc {
+        self.alloc
+    }
+
+    /// Returns `true` if the [`Span`] owning the allocation is still alive.
+    pub fn is_valid(&self) -> bool {
+        self.alloc.check().is_ok()
+    }
+
+    /// Returns the generation this pointer was created with.
+    pub fn generation(&self) -> u32 {
+        self.alloc.gen
+    }
+
+    /// Returns a copy of the pointed-to value.
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale.
+    /// See [`CopyPtr::try_get`] for a non-panicking variant.
+    pub fn get(&self) -> T {
+        match self.try_get() {
+            Ok(value) => value,
+            Err(err) => err.panic::<T>("CopyPtr", ""),
+        }
+    }
+
+    /// Overwrites the pointed-to value.
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale.
+    /// See [`CopyPtr::try_set`] for a non-panicking variant.
+    pub fn set(&self, v: T) {
+        if let Err(err) = self.try_set(v) {
+            err.panic::<T>("CopyPtr", "");
+        }
+    }
+
+    /// Returns a copy of the pointed-to value, or an error if the pointer is stale.
+    pub fn try_get(&self) -> Result<T, AccessError> {
+        self.alloc.check()?;
+        // SAFETY: The slot holds the `T` this pointer was created with until its generation
+        // changes.
+        Ok(unsafe { self.alloc.slot.value.as_ptr().cast::<T>().read() })
+    }
+
+    /// Overwrites the pointed-to value, or returns an error if the pointer is stale.
+    pub fn try_set(&self, v: T) -> Result<(), AccessError> {
+        self.alloc.check()?;
+        // SAFETY: The slot holds a `T` until its generation changes, and `T` needs no drop.
+        unsafe { self.alloc.slot.value.as_ptr().cast::<T>().write(v) };
+        Ok(())
+    }
+}
+
+/// Generational allocation of a [`Copy`] value.
+#[derive(Clone, Copy)]
+pub(crate) struct CopyAlloc {
+    slot: &'static CopySlot,
+    gen: u32,
+}
+
+impl CopyAlloc {
+    fn check(&self) -> Result<(), DanglingError> {
+        let actual = self.slot.gen.get();
+        if self.gen == actual {
+            Ok(())
+        } else {
+            Err(DanglingError::new(self.gen, actual))
+        }
+    }
+
+    /// Bumps the slot's generation and returns it to the recycled pool, unless it's retired.
+    pub(crate) fn recycle(self) {
+        if self.check().is_err() {
+            return;
+        }
+        let gen = self.gen + 1;
+        self.slot.gen.set(gen);
+        if gen != RETIRED_GEN {
+            RECYCLED_COPY_ALLOCS.with(|recycled| recycled.borrow_mut().push(self.slot));
+        }
+    }
+}
+
+/// Inline storage of a [`CopySlot`], large enough for e.g. a `[f32; 4]` or a pair of `u64`s.
+#[repr(C, align(16))]
+struct Storage {
+    _bytes: [MaybeUninit<u8>; 16],
+}
+
+/// Leaked storage shared by every generation of a [`Copy`] allocation.
+struct CopySlot {
+    value: Cell<MaybeUninit<Storage>>,
+    gen: Cell<u32>,
+}
+
+impl CopySlot {
+    fn leak() -> &'static Self {
+        Box::leak(Box::new(Self {
+            value: Cell::new(MaybeUninit::uninit()),
+            gen: Cell::new(0),
+        }))
+    }
+}
+
+thread_local! {
+    static RECYCLED_COPY_ALLOCS: RefCell<Vec<&'static CopySlot>> = const { RefCell::new(Vec::new()) };
+}
@@ -0,0 +1,42 @@
+//! Heap allocations made through the allocator of a pool, see [`PoolStorage::with_allocator`].
+
+use alloc::alloc::{handle_alloc_error, Layout};
+use core::{alloc::GlobalAlloc, ptr::NonNull};
+
+#[cfg(doc)]
+use crate::PoolStorage;
+
+/// Allocator of a pool, [`None`] for the global allocator.
+pub(crate) type Allocator = Option<&'static dyn GlobalAlloc>;
+
+/// Allocates memory for `layout`, which may have a zero size.
+pub(crate) fn alloc(allocator: Allocator, layout: Layout) -> NonNull<u8> {
+    if layout.size() == 0 {
+        return NonNull::new(core::ptr::without_provenance_mut(layout.align()))
+            .expect("alignment is never zero");
+    }
+    let ptr = match allocator {
+        // SAFETY: The layout has a non-zero size.
+        Some(allocator) => unsafe { allocator.alloc(layout) },
+        // SAFETY: As above.
+        None => unsafe { alloc::alloc::alloc(layout) },
+    };
+    NonNull::new(ptr).unwrap_or_else(|| handle_alloc_error(layout))
+}
+
+/// Frees memory returned by [`alloc`].
+///
+/// # Safety
+/// `ptr` must have been returned by [`alloc`] with the same `allocator` and `layout`, and must
+/// not be used afterwards.
+pub(crate) unsafe fn dealloc(allocator: Allocator, ptr: NonNull<u8>, layout: Layout) {
+    if layout.size() == 0 {
+        return;
+    }
+    match allocator {
+        // SAFETY: Guaranteed by the caller.
+        Some(allocator) => unsafe { allocator.dealloc(ptr.as_ptr(), layout) },
+        // SAFETY: As above.
+        None => unsafe { alloc::alloc::dealloc(ptr.as_ptr(), layout) },
+    }
+}
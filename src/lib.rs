@@ -1,20 +1,231 @@
-#![doc = include_str!("../README.md")]
+// The examples of the README need the `std` feature.
+#![cfg_attr(feature = "std", doc = include_str!("../README.md"))]
+#![cfg_attr(not(feature = "std"), no_std)]
 
-use std::{
-    any::Any,
-    cell::{Ref, RefCell, RefMut},
+extern crate alloc;
+
+use alloc::{
+    alloc::Layout,
+    boxed::Box,
+    collections::{BTreeSet, VecDeque},
+    format,
+    rc::{Rc, Weak},
+    vec::Vec,
+};
+use core::{
+    any::{Any, TypeId},
+    cell::{Cell, Ref, RefCell, RefMut},
+    cmp::Ordering,
+    error::Error,
+    fmt,
+    hash::{Hash, Hasher},
     marker::PhantomData,
+    mem::MaybeUninit,
+    pin::Pin,
+    ptr::NonNull,
 };
 
+#[cfg(feature = "std")]
+mod copy;
+#[cfg(feature = "std")]
+pub use copy::CopyPtr;
+mod heap;
+#[cfg(feature = "serde")]
+mod serde_impl;
+#[cfg(feature = "serde")]
+pub use serde_impl::SpanSerializer;
+#[cfg(feature = "sync")]
+pub mod sync;
+#[cfg(feature = "debug-tracking")]
+mod tracking;
+#[cfg(feature = "std")]
+pub mod typed;
+mod value;
+
+use value::Value;
+
+#[doc(hidden)]
+pub mod __private {
+    pub use alloc::boxed::Box;
+}
+
 /// Generational allocations span.
-#[derive(Default)]
-pub struct Span(Vec<Alloc>);
+///
+/// Recycles its allocations through the current thread's pool, or through a [`PoolStorage`]
+/// when created with [`Span::new_in`]. Without the `std` feature, the latter is the only option.
+/// With the `shared-pool` feature, the thread pools of exited threads are handed over to the
+/// threads that still allocate.
+pub struct Span {
+    allocs: Vec<Alloc>,
+    children: Vec<Weak<RefCell<Span>>>,
+    /// Pool to take allocations from, [`None`] for the current thread's pool.
+    pool: Option<&'static PoolStorage>,
+    /// Whether allocations bypass the pool, see [`Span::without_recycling`].
+    unpooled: bool,
+    name: Option<&'static str>,
+    /// Allocations made by [`Span::alloc_copy`].
+    #[cfg(feature = "std")]
+    copies: Vec<copy::CopyAlloc>,
+    /// Positions of the live [`Checkpoint`]s, moved whenever allocations are removed.
+    checkpoints: Vec<Weak<Cell<Mark>>>,
+    #[cfg(feature = "debug-spans")]
+    id: SpanId,
+}
+
+#[cfg(feature = "std")]
+impl Default for Span {
+    fn default() -> Self {
+        Self::empty(None)
+    }
+}
 
 impl Span {
+    /// Requires the `std` feature.
+    #[cfg(feature = "std")]
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Creates a [`Span`] taking its allocations from `pool` instead of the current thread's
+    /// pool.
+    pub fn new_in(pool: &'static PoolStorage) -> Self {
+        Self::empty(Some(pool))
+    }
+
+    /// Creates a [`Span`] whose allocations are never taken from nor returned to the current
+    /// thread's pool.
+    ///
+    /// Every allocation gets fresh storage, which is retired once the [`Span`] recycles it.
+    /// Useful for spans holding unusually large values, which would otherwise be scattered
+    /// across the pool. The values are still dropped as usual.
+    ///
+    /// **The storage of every allocation, about 150 bytes, is leaked for good**: stale pointers
+    /// may still check its generation, so it's neither reused nor freed, not even by
+    /// [`PoolStorage::drain`]. The leaked allocations are counted by [`SpanStats::retired`]. Don't
+    /// use such spans for an unbounded number of allocations.
+    ///
+    /// Requires the `std` feature.
+    #[cfg(feature = "std")]
+    pub fn without_recycling() -> Self {
+        let mut span = Self::empty(None);
+        span.unpooled = true;
+        span
+    }
+
+    fn empty(pool: Option<&'static PoolStorage>) -> Self {
+        Self {
+            allocs: Vec::new(),
+            children: Vec::new(),
+            pool,
+            unpooled: false,
+            name: None,
+            #[cfg(feature = "std")]
+            copies: Vec::new(),
+            checkpoints: Vec::new(),
+            #[cfg(feature = "debug-spans")]
+            id: SpanId::next(),
+        }
+    }
+
+    /// Creates a [`Span`] named `name`, see [`Span::with_name`].
+    ///
+    /// Requires the `std` feature.
+    #[cfg(feature = "std")]
+    pub fn named(name: &'static str) -> Self {
+        Self::new().with_name(name)
+    }
+
+    /// Names this [`Span`], e.g. after the part of the program using it.
+    ///
+    /// With the `debug-tracking` feature, failed accesses through pointers allocated by this
+    /// [`Span`] panic with its name and the location of the allocation.
+    #[must_use]
+    pub fn with_name(mut self, name: &'static str) -> Self {
+        self.name = Some(name);
+        self
+    }
+
+    /// Returns the name of this [`Span`], see [`Span::with_name`].
+    pub fn name(&self) -> Option<&'static str> {
+        self.name
+    }
+
+    /// Returns the id of this [`Span`], which shows up in the [`DanglingError`]s of its
+    /// pointers.
+    ///
+    /// Requires the `debug-spans` feature.
+    #[cfg(feature = "debug-spans")]
+    pub fn id(&self) -> SpanId {
+        self.id
+    }
+
+    /// Runs `f` with a fresh [`Span`] and recycles its allocations once `f` returns or panics.
+    ///
+    /// Pointers allocated inside the scope are stale as soon as it ends, so returning them from
+    /// `f` is pointless.
+    ///
+    /// Requires the `std` feature.
+    #[cfg(feature = "std")]
+    pub fn scope<R>(f: impl FnOnce(&mut Span) -> R) -> R {
+        f(&mut Span::new())
+    }
+
+    /// Creates a [`Span`] able to hold at least `capacity` allocations without reallocating.
+    ///
+    /// Requires the `std` feature.
+    #[cfg(feature = "std")]
+    pub fn with_capacity(capacity: usize) -> Self {
+        let mut span = Self::empty(None);
+        span.allocs.reserve_exact(capacity);
+        span
+    }
+
+    /// Returns the number of allocations this [`Span`] can hold without reallocating, see
+    /// [`Span::reserve`].
+    pub fn capacity(&self) -> usize {
+        self.allocs.capacity()
+    }
+
+    /// Reserves capacity for at least `additional` more allocations.
+    ///
+    /// This is the capacity of the list tracking the allocations of this [`Span`], the
+    /// allocations themselves come from the recycled pool, see [`reserve_pool`].
+    pub fn reserve(&mut self, additional: usize) {
+        self.allocs.reserve(additional);
+    }
+
+    /// Shrinks the capacity of this [`Span`] as much as possible, e.g. after clearing it
+    /// following a spike of allocations.
+    ///
+    /// Like [`Span::reserve`] and [`Span::with_capacity`], this only affects the list tracking
+    /// the allocations of this [`Span`]. Recycled allocations stay in the pool, see
+    /// [`shrink_pool`].
+    pub fn shrink_to_fit(&mut self) {
+        self.shrink_to(0);
+    }
+
+    /// Shrinks the capacity of this [`Span`] to at least `min_capacity` allocations, see
+    /// [`Span::shrink_to_fit`].
+    pub fn shrink_to(&mut self, min_capacity: usize) {
+        self.prune();
+        self.allocs.shrink_to(min_capacity);
+        #[cfg(feature = "std")]
+        self.copies.shrink_to(min_capacity);
+    }
+
+    /// Creates a child [`Span`].
+    ///
+    /// Clearing or dropping this [`Span`] also recycles all allocations of its children, while
+    /// dropping a child leaves the allocations of this [`Span`] untouched.
+    pub fn child(&mut self) -> ChildSpan {
+        let mut child = Span::empty(self.pool);
+        child.unpooled = self.unpooled;
+        let child = Rc::new(RefCell::new(child));
+        self.children.retain(|child| child.strong_count() > 0);
+        self.children.push(Rc::downgrade(&child));
+        ChildSpan(child)
+    }
+
     /// Allocates `v` on the heap and stores the pointer in a generational allocation.
     ///
     /// Then the [`Span`] gets dropped it recycles it's generational allocations so that they
@@ -22,91 +233,5382 @@ impl Span {
     ///
     /// The returned [`Ptr<T>`] is [`Copy`] even if the underlying `T` is not [`Copy`].
     /// This pointer gets invalidated whenever it's [`Span`] is dropped.
+    ///
+    /// Values of up to 24 bytes, aligned to at most 8 bytes, are stored inline without touching
+    /// the heap. Recycled allocations keep the heap allocation of their last larger value, so
+    /// allocating a value of the same type again, e.g. every frame, doesn't touch the heap
+    /// either. The `fresh_allocs` benchmark counts the heap allocations of both cases.
+    /// Zero-sized values never touch the heap, but still take an allocation from the pool, as
+    /// every [`Ptr`] has to be invalidated on its own, e.g. by [`Span::free`].
     #[must_use]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
     pub fn alloc<T: 'static>(&mut self, v: T) -> Ptr<T> {
-        let alloc = RECYCLED_ALLOCS
-            .with(|recycled| recycled.borrow_mut().pop())
-            .unwrap_or_default();
-        self.0.push(alloc);
-        *alloc.ptr.borrow_mut() = Some(Box::new(v));
+        let slot = self.take_slot();
+        let value = slot.boxed(v);
         Ptr {
-            gen: alloc.gen,
-            alloc,
+            alloc: self.store(slot, value, Some(core::any::type_name::<T>())),
             _marker: PhantomData,
         }
     }
-}
 
-impl Drop for Span {
-    fn drop(&mut self) {
-        RECYCLED_ALLOCS.with(|recycled| {
-            let mut recycled = recycled.borrow_mut();
-            for alloc in &mut self.0 {
-                let _ = alloc.ptr.take();
-                alloc.gen += 1;
-                recycled.push(*alloc)
+    /// Stores the value returned by `f` in a generational allocation, passing `f` the pointer to
+    /// it, like [`Rc::new_cyclic`].
+    ///
+    /// Until `f` returns, the allocation is live but empty, so reading it fails with
+    /// [`AccessError::TypeMismatch`] and no `actual` type.
+    /// ```
+    /// struct Node {
+    ///     next: genalloc::Ptr<Node>,
+    /// }
+    ///
+    /// # #[cfg(feature = "std")] {
+    /// let mut span = genalloc::Span::new();
+    /// let node = span.alloc_cyclic(|node| Node { next: node });
+    /// assert_eq!(node.read().next, node);
+    /// # }
+    /// ```
+    #[must_use]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn alloc_cyclic<T: 'static>(&mut self, f: impl FnOnce(Ptr<T>) -> T) -> Ptr<T> {
+        let slot = self.take_slot();
+        let ptr = Ptr {
+            alloc: self.store(slot, Value::Empty, Some(core::any::type_name::<T>())),
+            _marker: PhantomData,
+        };
+        let value = f(ptr);
+        *slot.value.borrow_mut() = slot.boxed(value);
+        ptr
+    }
+
+    /// Stores `v` in a generational allocation that gets recycled as soon as the returned
+    /// [`Owned<T>`] drops, see [`Span::alloc`].
+    ///
+    /// Dropping this [`Span`] first still invalidates the allocation.
+    /// ```
+    /// # #[cfg(feature = "std")] {
+    /// let mut span = genalloc::Span::new();
+    /// let owned = span.alloc_owned(5);
+    /// let ptr = *owned;
+    /// assert_eq!(*owned.read(), 5);
+    /// drop(owned);
+    /// assert!(!ptr.is_valid());
+    /// assert!(span.is_empty());
+    /// # }
+    /// ```
+    #[must_use]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn alloc_owned<T: 'static>(&mut self, v: T) -> Owned<T> {
+        Owned { ptr: self.alloc(v) }
+    }
+
+    /// Stores `v` in a generational allocation that never moves it until it's dropped, see
+    /// [`PinnedPtr`].
+    ///
+    /// The value is always boxed. Accesses through a [`Ptr`] to the allocation, e.g. from
+    /// [`Span::iter_of`], fail with [`AccessError::Pinned`] if they could move the value,
+    /// including [`Ptr::write`], which has to be replaced by [`Ptr::write_pinned`].
+    /// [`Span::into_erased`] drops pinned values instead of returning them.
+    #[must_use]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn alloc_pinned<T: 'static>(&mut self, v: T) -> PinnedPtr<T> {
+        let slot = self.take_slot();
+        let alloc = self.store(slot, slot.allocated(v), Some(core::any::type_name::<T>()));
+        slot.pinned.set(true);
+        PinnedPtr {
+            ptr: Ptr {
+                alloc,
+                _marker: PhantomData,
+            },
+        }
+    }
+
+    /// Stores the small [`Copy`] value `v` inline in a generational allocation, see
+    /// [`Span::alloc`].
+    ///
+    /// The returned [`CopyPtr<T>`] accesses the value without any borrow guards, which makes it
+    /// cheaper than a [`Ptr<T>`] for e.g. timers and counters. Fails to compile for values
+    /// larger than 16 bytes.
+    /// ```
+    /// let mut span = genalloc::Span::new();
+    /// let timer = span.alloc_copy(0.0f32);
+    /// timer.set(timer.get() + 0.5);
+    /// assert_eq!(timer.get(), 0.5);
+    /// ```
+    ///
+    /// Requires the `std` feature.
+    #[cfg(feature = "std")]
+    #[must_use]
+    pub fn alloc_copy<T: Copy + 'static>(&mut self, v: T) -> CopyPtr<T> {
+        let ptr = CopyPtr::new(v);
+        self.copies.push(ptr.alloc());
+        ptr
+    }
+
+    /// Stores the value returned by `f` in a generational allocation, see [`Span::alloc`].
+    ///
+    /// The value's heap allocation is made before `f` runs, which lets optimized builds write
+    /// the value straight into it instead of building it on the stack first. Prefer this over
+    /// [`Span::alloc`] for large values, e.g. big arrays. Debug builds may still build the
+    /// value on the stack. Values that fit inline are built first and stored just like by
+    /// [`Span::alloc`].
+    ///
+    /// If `f` panics, the allocation taken for its value is returned to the pool.
+    #[must_use]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn alloc_with<T: 'static>(&mut self, f: impl FnOnce() -> T) -> Ptr<T> {
+        if Value::fits_inline::<T>() {
+            return self.alloc(f());
+        }
+        let slot = self.take_slot();
+        let unused = UnusedSlot(slot);
+        let value = slot.boxed_with(f);
+        core::mem::forget(unused);
+        Ptr {
+            alloc: self.store(slot, value, Some(core::any::type_name::<T>())),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Stores `T::default()` in a generational allocation, see [`Span::alloc_with`].
+    #[must_use]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn alloc_default<T: Default + 'static>(&mut self) -> Ptr<T> {
+        self.alloc_with(T::default)
+    }
+
+    /// Stores the value returned by `f` in a generational allocation, or returns its error
+    /// without allocating anything, see [`Span::alloc_with`].
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn try_alloc_with<T: 'static, E>(
+        &mut self,
+        f: impl FnOnce() -> Result<T, E>,
+    ) -> Result<Ptr<T>, E> {
+        Ok(self.alloc(f()?))
+    }
+
+    /// Stores the already boxed `v` in a generational allocation, see [`Span::alloc`].
+    ///
+    /// This allows allocating unsized values, e.g. trait objects implementing [`Pointee`]:
+    /// ```
+    /// trait Named {
+    ///     fn name(&self) -> &str;
+    /// }
+    ///
+    /// impl Named for String {
+    ///     fn name(&self) -> &str {
+    ///         self
+    ///     }
+    /// }
+    ///
+    /// genalloc::pointee!(dyn Named);
+    ///
+    /// # #[cfg(feature = "std")] {
+    /// let mut span = genalloc::Span::new();
+    /// let ptr: genalloc::Ptr<dyn Named> = span.alloc_boxed(Box::new("test".to_string()));
+    /// assert_eq!(ptr.read().name(), "test");
+    /// # }
+    /// ```
+    #[must_use]
+    #[doc(alias = "alloc_dyn")]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn alloc_boxed<T: ?Sized + Pointee>(&mut self, v: Box<T>) -> Ptr<T> {
+        Ptr {
+            alloc: self.alloc_any(T::into_any(v).into(), Some(core::any::type_name::<T>())),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Stores the elements of `values` contiguously in a generational allocation, see
+    /// [`Span::alloc`].
+    #[must_use]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn alloc_slice<T: 'static>(&mut self, values: Vec<T>) -> Ptr<[T]> {
+        self.alloc_boxed(values.into_boxed_slice())
+    }
+
+    /// Collects `iter` into a slice stored in a generational allocation, see
+    /// [`Span::alloc_slice`].
+    #[must_use]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn alloc_from_iter<T: 'static>(&mut self, iter: impl IntoIterator<Item = T>) -> Ptr<[T]> {
+        self.alloc_boxed(iter.into_iter().collect())
+    }
+
+    /// Stores a slice of `len` elements returned by `f` for their index in a generational
+    /// allocation, see [`Span::alloc_slice`].
+    #[must_use]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn alloc_slice_fill_with<T: 'static>(
+        &mut self,
+        len: usize,
+        f: impl FnMut(usize) -> T,
+    ) -> Ptr<[T]> {
+        self.alloc_boxed((0..len).map(f).collect())
+    }
+
+    /// Copies `values` into a slice stored in a generational allocation, see
+    /// [`Span::alloc_slice`].
+    #[must_use]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn alloc_slice_copy<T: Copy + 'static>(&mut self, values: &[T]) -> Ptr<[T]> {
+        self.alloc_boxed(values.into())
+    }
+
+    /// Copies `s` into a generational allocation, see [`Span::alloc`].
+    #[must_use]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn alloc_str(&mut self, s: &str) -> Ptr<str> {
+        self.alloc_boxed(s.into())
+    }
+
+    /// Stores every value of `iter` in its own generational allocation, see [`Span::alloc`].
+    ///
+    /// Behaves like calling [`Span::alloc`] for every value, but takes the allocations from the
+    /// recycled pool in bulk. `iter` is collected before any allocation is made.
+    #[must_use]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn alloc_iter<T: 'static>(&mut self, iter: impl IntoIterator<Item = T>) -> Vec<Ptr<T>> {
+        let values: Vec<T> = iter.into_iter().collect();
+        if self.unpooled {
+            let mut ptrs = Vec::with_capacity(values.len());
+            for value in values {
+                ptrs.push(self.alloc(value));
+            }
+            return ptrs;
+        }
+        let mut slots = Vec::with_capacity(values.len());
+        with_pool(self.pool, |pool| {
+            pool.take_recycled(values.len(), &mut slots);
+            if slots.len() < values.len() {
+                pool.reclaim();
+            }
+        });
+        while slots.len() < values.len() {
+            with_pool(self.pool, |pool| {
+                pool.take_recycled(values.len() - slots.len(), &mut slots)
+            });
+            if slots.len() < values.len() {
+                slots.push(Slot::leak(self.pool));
+            }
+        }
+        with_pool(self.pool, |pool| {
+            for slot in &slots {
+                pool.record_alloc(slot);
+            }
+        });
+        self.allocs.reserve(values.len());
+        let mut ptrs = Vec::with_capacity(values.len());
+        for (value, slot) in values.into_iter().zip(slots) {
+            ptrs.push(Ptr {
+                alloc: self.store(slot, slot.boxed(value), Some(core::any::type_name::<T>())),
+                _marker: PhantomData,
+            });
+        }
+        ptrs
+    }
+
+    /// Stores `value` in a generational allocation of this [`Span`].
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    fn alloc_any(&mut self, value: Value, type_name: Option<&'static str>) -> Alloc {
+        let slot = self.take_slot();
+        self.store(slot, value, type_name)
+    }
+
+    /// Takes a slot from the recycled pool, or leaks fresh ones if it's empty.
+    fn take_slot(&self) -> &'static Slot {
+        if self.unpooled {
+            return Slot::leak_retiring(self.pool);
+        }
+        with_pool(self.pool, |pool| {
+            let mut slot = pool.pop_recycled();
+            if slot.is_none() {
+                pool.reclaim();
+                slot = pool.pop_recycled();
+            }
+            let slot = slot.unwrap_or_else(|| Slot::leak(self.pool));
+            pool.record_alloc(slot);
+            slot
+        })
+    }
+
+    /// Stores `value` of type `type_name` in `slot`, which was just taken from the recycled pool.
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    fn store(
+        &mut self,
+        slot: &'static Slot,
+        value: Value,
+        type_name: Option<&'static str>,
+    ) -> Alloc {
+        *slot.value.borrow_mut() = value;
+        slot.type_name.set(type_name);
+        slot.poisoned.set(false);
+        slot.pinned.set(false);
+        let alloc = Alloc {
+            slot,
+            gen: slot.gen.get(),
+            id: Alloc::next_id(),
+            #[cfg(feature = "debug-spans")]
+            span: Some(self.id),
+        };
+        if self.allocs.len() == self.allocs.capacity() {
+            self.prune();
+        }
+        self.allocs.push(alloc.list());
+        #[cfg(feature = "debug-tracking")]
+        tracking::record(alloc, self.name);
+        alloc
+    }
+
+    /// Returns the number of live allocations in this [`Span`].
+    ///
+    /// Every [`Span::alloc`] call counts until its value is individually freed, e.g. with
+    /// [`Span::free`] or [`Ptr::take`], or the [`Span`] gets cleared.
+    #[doc(alias = "live_count")]
+    pub fn len(&self) -> usize {
+        #[cfg(feature = "std")]
+        let copies = self.copies.len();
+        #[cfg(not(feature = "std"))]
+        let copies = 0;
+        self.live_allocs().count() + copies
+    }
+
+    /// Returns the number of live allocations of this [`Span`] holding a `T`, see
+    /// [`Span::iter_of`].
+    pub fn count_of<T: ?Sized + Pointee>(&self) -> usize {
+        self.iter_of::<T>().count()
+    }
+
+    /// Returns `true` if this [`Span`] holds no live allocations.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Returns pointers to the live allocations of this [`Span`] holding a `T`, in allocation
+    /// order.
+    ///
+    /// Allocations that are currently mutably borrowed are skipped, as their type can't be
+    /// checked.
+    /// ```
+    /// # #[cfg(feature = "std")] {
+    /// let mut span = genalloc::Span::new();
+    /// let _ = (span.alloc(1), span.alloc("two"), span.alloc(3));
+    /// for ptr in span.iter_of::<i32>() {
+    ///     *ptr.write() *= 10;
+    /// }
+    /// assert!(span.iter_of::<i32>().map(|ptr| ptr.get()).eq([10, 30]));
+    /// # }
+    /// ```
+    #[doc(alias = "iter_ptrs")]
+    pub fn iter_of<T: ?Sized + Pointee>(&self) -> impl Iterator<Item = Ptr<T>> + '_ {
+        self.live_allocs()
+            .filter_map(|alloc| ErasedPtr { alloc }.downcast())
+    }
+
+    /// Returns the position of `ptr` among the live allocations of this [`Span`], in allocation
+    /// order.
+    ///
+    /// Returns [`None`] if `ptr` is stale or was allocated by another [`Span`].
+    pub fn index_of<T: ?Sized>(&self, ptr: Ptr<T>) -> Option<usize> {
+        self.live_allocs().position(|alloc| alloc == ptr.alloc)
+    }
+
+    /// Returns a pointer to the live allocation at `index`, see [`Span::index_of`].
+    pub fn ptr_at(&self, index: usize) -> Option<ErasedPtr> {
+        let alloc = self.live_allocs().nth(index)?;
+        Some(ErasedPtr { alloc })
+    }
+
+    fn live_allocs(&self) -> impl Iterator<Item = Alloc> + '_ {
+        self.allocs
+            .iter()
+            .copied()
+            .filter(|alloc| alloc.check().is_ok())
+    }
+
+    /// Calls `f` with every value held by this [`Span`].
+    ///
+    /// Allocations that were freed or are currently mutably borrowed are skipped.
+    pub fn for_each_any(&self, mut f: impl FnMut(&dyn Any)) {
+        for alloc in &self.allocs {
+            if alloc.check().is_err() {
+                continue;
+            }
+            if let Ok(value) = alloc.slot.value.try_borrow() {
+                if let Some(value) = value.as_deref() {
+                    f(value);
+                }
+            }
+        }
+    }
+
+    /// Returns the number of values held by this [`Span`] per type, e.g. to find out what fills
+    /// it up.
+    ///
+    /// Values of unsized types are counted under the type of their [`Box`], see [`pointee!`].
+    /// Like in [`Span::for_each_any`], mutably borrowed values are skipped.
+    ///
+    /// Requires the `std` feature.
+    #[cfg(feature = "std")]
+    pub fn type_histogram(&self) -> std::collections::HashMap<TypeId, usize> {
+        let mut histogram = std::collections::HashMap::new();
+        self.for_each_any(|value| *histogram.entry(value.type_id()).or_default() += 1);
+        histogram
+    }
+
+    /// Moves the value out of an allocation of this [`Span`] and recycles it without waiting for
+    /// the [`Span`] to drop.
+    ///
+    /// All copies of `ptr` become stale.
+    pub fn free<T: 'static>(&mut self, ptr: Ptr<T>) -> Result<T, FreeError> {
+        ptr.alloc.check().map_err(AccessError::from)?;
+        let index = self
+            .allocs
+            .iter()
+            .position(|alloc| *alloc == ptr.alloc)
+            .ok_or(FreeError::Foreign)?;
+        let value = ptr.take()?;
+        self.remove_alloc(index);
+        Ok(value)
+    }
+
+    /// Drops the value of an allocation of this [`Span`] and recycles it without waiting for the
+    /// [`Span`] to drop, see [`Span::free`].
+    ///
+    /// Returns `false` without touching the allocation if `ptr` is stale, belongs to a different
+    /// [`Span`] or its value is currently borrowed.
+    pub fn dealloc<T: ?Sized>(&mut self, ptr: Ptr<T>) -> bool {
+        if ptr.alloc.check().is_err() || ptr.alloc.slot.value.try_borrow_mut().is_err() {
+            return false;
+        }
+        let Some(index) = self.allocs.iter().position(|alloc| *alloc == ptr.alloc) else {
+            return false;
+        };
+        self.remove_alloc(index).release();
+        true
+    }
+
+    /// Moves the allocation of `ptr` from this [`Span`] into `dst`.
+    ///
+    /// `ptr` and its copies stay valid, but are now invalidated by clearing or dropping `dst`
+    /// instead of this [`Span`].
+    pub fn transfer<T: ?Sized>(
+        &mut self,
+        ptr: Ptr<T>,
+        dst: &mut Span,
+    ) -> Result<(), TransferError> {
+        ptr.alloc.check()?;
+        let index = self
+            .allocs
+            .iter()
+            .position(|alloc| *alloc == ptr.alloc)
+            .ok_or(TransferError::Foreign)?;
+        let alloc = self.remove_alloc(index);
+        #[cfg(feature = "debug-spans")]
+        let alloc = Alloc {
+            span: Some(dst.id),
+            ..alloc
+        };
+        dst.allocs.push(alloc.list());
+        Ok(())
+    }
+
+    /// Moves all allocations and children of `other` into this [`Span`], leaving `other` empty.
+    ///
+    /// Pointers allocated by `other` stay valid, but are now invalidated by clearing or dropping
+    /// this [`Span`] instead of `other`.
+    pub fn append(&mut self, other: &mut Span) {
+        #[cfg(feature = "debug-spans")]
+        for alloc in &mut other.allocs {
+            alloc.span = Some(self.id);
+        }
+        self.allocs.append(&mut other.allocs);
+        #[cfg(feature = "std")]
+        self.copies.append(&mut other.copies);
+        Self::move_checkpoints(&mut other.checkpoints, |mark| *mark = Mark::default());
+        self.children.append(&mut other.children);
+    }
+
+    /// Takes over all allocations and children of `other`, see [`Span::append`].
+    ///
+    /// Pointers allocated by `other` stay valid until this [`Span`] is cleared or dropped.
+    pub fn absorb(&mut self, mut other: Span) {
+        self.append(&mut other);
+    }
+
+    /// Recycles all allocations of this [`Span`] while keeping it around for reuse.
+    ///
+    /// All pointers allocated by this [`Span`] and its children get invalidated, just as if it was
+    /// dropped. The capacity of this [`Span`] is kept, and the next allocations reuse the
+    /// allocations recycled last.
+    #[doc(alias = "reset")]
+    pub fn clear(&mut self) {
+        for alloc in self.allocs.drain(..) {
+            alloc.unlist().release();
+        }
+        Self::move_checkpoints(&mut self.checkpoints, |mark| *mark = Mark::default());
+        #[cfg(feature = "std")]
+        for alloc in self.copies.drain(..) {
+            alloc.recycle();
+        }
+        for child in &self.children {
+            if let Some(child) = child.upgrade() {
+                child.borrow_mut().clear();
+            }
+        }
+        with_pool(self.pool, PoolStorage::reclaim);
+    }
+
+    /// Marks the current end of this [`Span`], so that [`Span::rollback`] can recycle all
+    /// allocations made after it, e.g. for nested scratch regions inside a long-lived [`Span`].
+    pub fn checkpoint(&mut self) -> Checkpoint {
+        let mark = Rc::new(Cell::new(Mark {
+            allocs: self.allocs.len(),
+            #[cfg(feature = "std")]
+            copies: self.copies.len(),
+        }));
+        self.checkpoints.push(Rc::downgrade(&mark));
+        Checkpoint(mark)
+    }
+
+    /// Recycles all allocations made after `checkpoint` was taken, see [`Span::checkpoint`].
+    ///
+    /// Pointers allocated before the checkpoint stay valid, later ones get invalidated, just as
+    /// if they were freed. Allocations of children are left untouched. Checkpoints taken after
+    /// `checkpoint` now roll back to the same position.
+    ///
+    /// # Panics
+    /// Panics if `checkpoint` was taken on a different [`Span`].
+    #[doc(alias = "rollback_to")]
+    pub fn rollback(&mut self, checkpoint: Checkpoint) {
+        assert!(
+            self.checkpoints
+                .iter()
+                .any(|mark| core::ptr::eq(mark.as_ptr(), Rc::as_ptr(&checkpoint.0))),
+            "checkpoint was taken on a different span"
+        );
+        let end = checkpoint.0.get();
+        drop(checkpoint);
+        for alloc in self.allocs.drain(end.allocs..) {
+            alloc.unlist().release();
+        }
+        #[cfg(feature = "std")]
+        for alloc in self.copies.drain(end.copies..) {
+            alloc.recycle();
+        }
+        Self::move_checkpoints(&mut self.checkpoints, |mark| {
+            mark.allocs = mark.allocs.min(end.allocs);
+            #[cfg(feature = "std")]
+            {
+                mark.copies = mark.copies.min(end.copies);
             }
         });
+        with_pool(self.pool, PoolStorage::reclaim);
     }
-}
 
-/// Generational pointer.
-///
-/// [`Ptr<T>`] is [`Copy`] even if the underlying `T` is not [`Copy`].
-pub struct Ptr<T> {
-    alloc: Alloc,
-    gen: u32,
-    _marker: PhantomData<T>,
+    /// Updates the positions of the live checkpoints and forgets the dropped ones.
+    fn move_checkpoints(checkpoints: &mut Vec<Weak<Cell<Mark>>>, f: impl Fn(&mut Mark)) {
+        checkpoints.retain(|checkpoint| {
+            let Some(checkpoint) = checkpoint.upgrade() else {
+                return false;
+            };
+            let mut mark = checkpoint.get();
+            f(&mut mark);
+            checkpoint.set(mark);
+            true
+        });
+    }
+
+    /// Removes the allocation at `index` from the list, without recycling it.
+    fn remove_alloc(&mut self, index: usize) -> Alloc {
+        Self::move_checkpoints(&mut self.checkpoints, |mark| {
+            if mark.allocs > index {
+                mark.allocs -= 1;
+            }
+        });
+        self.allocs.remove(index).unlist()
+    }
+
+    /// Removes allocations recycled individually, e.g. by [`Owned`], from the list.
+    fn prune(&mut self) {
+        let allocs = &self.allocs;
+        Self::move_checkpoints(&mut self.checkpoints, |mark| {
+            mark.allocs = allocs[..mark.allocs]
+                .iter()
+                .filter(|alloc| alloc.check().is_ok())
+                .count();
+        });
+        self.allocs.retain(|alloc| {
+            let live = alloc.check().is_ok();
+            if !live {
+                alloc.unlist();
+            }
+            live
+        });
+    }
+
+    /// Moves the values of all allocations out of this [`Span`] in allocation order, then drops
+    /// it.
+    ///
+    /// Values of unsized types are returned in an additional [`Box`], see [`pointee!`]. Values
+    /// that are currently borrowed or pinned by a [`KeepAlive`] are left to be recycled once
+    /// released, just as if this [`Span`] was dropped. Values of [`Span::alloc_pinned`] are
+    /// dropped in place.
+    pub fn into_erased(mut self) -> Vec<Box<dyn Any>> {
+        self.allocs
+            .drain(..)
+            .map(Alloc::unlist)
+            .filter_map(|alloc| {
+                if alloc.slot.pinned.get() {
+                    alloc.release();
+                    return None;
+                }
+                alloc.recycle().into_box()
+            })
+            .collect()
+    }
 }
 
-impl<T> Clone for Ptr<T> {
-    fn clone(&self) -> Self {
-        *self
+/// The alternate format (`{:#?}`) also lists the type, generation and borrow state of every live
+/// allocation, without borrowing the values.
+impl fmt::Debug for Span {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let alternate = f.alternate();
+        let mut f = f.debug_struct("Span");
+        if let Some(name) = self.name {
+            f.field("name", &name);
+        }
+        f.field("len", &self.len())
+            .field("capacity", &self.capacity());
+        if alternate {
+            let allocs: Vec<_> = self.live_allocs().map(AllocDebug).collect();
+            f.field("allocs", &allocs);
+        }
+        f.finish_non_exhaustive()
     }
 }
 
-impl<T> Copy for Ptr<T> {}
+/// Describes an allocation without borrowing its value, see the [`fmt::Debug`] impl of [`Span`].
+struct AllocDebug(Alloc);
 
-impl<T> Ptr<T> {
-    pub fn read(&self) -> Ref<'static, T> {
-        assert_eq!(self.gen, self.alloc.gen);
-        let borrow = self.alloc.ptr.borrow();
-        Ref::filter_map(borrow, |any| any.as_ref()?.downcast_ref()).unwrap()
+impl fmt::Debug for AllocDebug {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let slot = self.0.slot;
+        f.debug_struct(slot.type_name.get().unwrap_or("<unknown>"))
+            .field("gen", &self.0.gen)
+            .field("borrow", &self.0.borrow_state())
+            .finish()
     }
+}
 
-    pub fn write(&self) -> RefMut<'static, T> {
-        assert_eq!(self.gen, self.alloc.gen);
-        let borrow = self.alloc.ptr.borrow_mut();
-        RefMut::filter_map(borrow, |any| any.as_mut()?.downcast_mut()).unwrap()
+impl Drop for Span {
+    fn drop(&mut self) {
+        self.clear();
     }
 }
 
-/// Generational allocation.
-#[derive(Clone, Copy)]
-struct Alloc {
-    ptr: &'static RefCell<Option<Box<dyn Any>>>,
-    gen: u32,
+/// Position in a [`Span`] to roll back to, see [`Span::checkpoint`].
+#[derive(Debug)]
+pub struct Checkpoint(Rc<Cell<Mark>>);
+
+/// Ends of the allocation lists of a [`Span`] when a [`Checkpoint`] was taken.
+#[derive(Debug, Clone, Copy, Default)]
+struct Mark {
+    allocs: usize,
+    #[cfg(feature = "std")]
+    copies: usize,
 }
 
-impl Default for Alloc {
-    fn default() -> Self {
-        Self {
-            ptr: &*Box::leak(Default::default()),
-            gen: 0,
-        }
+/// Child of a [`Span`], see [`Span::child`].
+///
+/// Its allocations are recycled when either the child or its parent gets cleared or dropped.
+pub struct ChildSpan(Rc<RefCell<Span>>);
+
+impl ChildSpan {
+    /// Creates a child of this [`ChildSpan`], see [`Span::child`].
+    pub fn child(&mut self) -> ChildSpan {
+        self.0.borrow_mut().child()
+    }
+
+    /// Allocates `v` on the heap and stores the pointer in a generational allocation,
+    /// see [`Span::alloc`].
+    #[must_use]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn alloc<T: 'static>(&mut self, v: T) -> Ptr<T> {
+        self.0.borrow_mut().alloc(v)
+    }
+
+    /// Stores the already boxed `v` in a generational allocation, see [`Span::alloc_boxed`].
+    #[must_use]
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    pub fn alloc_boxed<T: ?Sized + Pointee>(&mut self, v: Box<T>) -> Ptr<T> {
+        self.0.borrow_mut().alloc_boxed(v)
+    }
+
+    /// Returns the number of live allocations in this [`ChildSpan`], see [`Span::len`].
+    pub fn len(&self) -> usize {
+        self.0.borrow().len()
+    }
+
+    /// Returns `true` if this [`ChildSpan`] holds no live allocations.
+    pub fn is_empty(&self) -> bool {
+        self.0.borrow().is_empty()
+    }
+
+    /// Recycles all allocations of this [`ChildSpan`] and its children, see [`Span::clear`].
+    pub fn clear(&mut self) {
+        self.0.borrow_mut().clear();
     }
 }
 
-thread_local! {
-    static RECYCLED_ALLOCS: RefCell<Vec<Alloc>> = const {
-        RefCell::new(Vec::new())
-    };
+/// Allocators of generational pointers, so that code can take any of them, e.g. a test double
+/// wrapping a [`Span`].
+/// ```
+/// use genalloc::{GenAlloc, Ptr, Span};
+///
+/// fn spawn(alloc: &mut impl GenAlloc) -> Ptr<String> {
+///     alloc.alloc("player".to_string())
+/// }
+///
+/// # #[cfg(feature = "std")] {
+/// let mut span = Span::new();
+/// let mut child = span.child();
+/// assert_eq!(*spawn(&mut span).read(), *spawn(&mut child).read());
+/// # }
+/// ```
+pub trait GenAlloc {
+    /// Stores `v` in a generational allocation, see [`Span::alloc`].
+    #[must_use]
+    fn alloc<T: 'static>(&mut self, v: T) -> Ptr<T>;
+
+    /// Stores the already boxed `v` in a generational allocation, see [`Span::alloc_boxed`].
+    #[must_use]
+    fn alloc_boxed<T: ?Sized + Pointee>(&mut self, v: Box<T>) -> Ptr<T>;
 }
 
+impl GenAlloc for Span {
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    fn alloc<T: 'static>(&mut self, v: T) -> Ptr<T> {
+        Span::alloc(self, v)
+    }
+
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    fn alloc_boxed<T: ?Sized + Pointee>(&mut self, v: Box<T>) -> Ptr<T> {
+        Span::alloc_boxed(self, v)
+    }
+}
+
+impl GenAlloc for ChildSpan {
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    fn alloc<T: 'static>(&mut self, v: T) -> Ptr<T> {
+        ChildSpan::alloc(self, v)
+    }
+
+    #[cfg_attr(feature = "debug-tracking", track_caller)]
+    fn alloc_boxed<T: ?Sized + Pointee>(&mut self, v: Box<T>) -> Ptr<T> {
+        ChildSpan::alloc_boxed(self, v)
+    }
+}
+
+/// Generational pointer.
+///
+/// [`Ptr<T>`] is [`Copy`] even if the underlying `T` is not [`Copy`].
+///
+/// Prefer [`Ptr::with`] and [`Ptr::with_mut`] over [`Ptr::read`] and [`Ptr::write`], they release
+/// the borrow before returning so it can't conflict with later accesses through other copies.
+pub struct Ptr<T: ?Sized> {
+    alloc: Alloc,
+    _marker: PhantomData<T>,
+}
+
+impl<T: ?Sized> Clone for Ptr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized> Copy for Ptr<T> {}
+
+impl<T: ?Sized> fmt::Debug for Ptr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct(&format!("Ptr<{}>", core::any::type_name::<T>()))
+            .field("slot", &core::ptr::from_ref(self.alloc.slot))
+            .field("gen", &self.alloc.gen)
+            .field("slot_gen", &self.alloc.slot.gen.get())
+            .field("live", &self.alloc.check().is_ok())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Pointers are equal if they point to the same generation of the same allocation.
+impl<T: ?Sized> PartialEq for Ptr<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.alloc == other.alloc
+    }
+}
+
+impl<T: ?Sized> Eq for Ptr<T> {}
+
+impl<T: ?Sized> Hash for Ptr<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.alloc.hash(state);
+    }
+}
+
+/// Orders pointers by [`Ptr::id`].
+impl<T: ?Sized> PartialOrd for Ptr<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T: ?Sized> Ord for Ptr<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // Ids are only unique per thread, the address keeps the order consistent with `Eq` for
+        // pointers of different threads.
+        self.id().cmp(&other.id()).then_with(|| {
+            let key = |ptr: &Self| (core::ptr::from_ref(ptr.alloc.slot), ptr.alloc.gen);
+            key(self).cmp(&key(other))
+        })
+    }
+}
+
+/// Returns [`Ptr::dangling`].
+impl<T: ?Sized> Default for Ptr<T> {
+    fn default() -> Self {
+        Self::dangling()
+    }
+}
+
+impl<T: ?Sized> Ptr<T> {
+    /// Returns the id of the allocation, counting the allocations made on the current thread.
+    ///
+    /// Ids increase in allocation order, also when an allocation reuses recycled storage. Unlike
+    /// addresses, they don't depend on the global allocator or on other threads, so ordering
+    /// pointers by them, e.g. in a [`BTreeMap`](alloc::collections::BTreeMap), gives the same
+    /// order in every run of a deterministic thread. Equal pointers have equal ids, and distinct
+    /// pointers of the same thread distinct ids, including stale ones. [`Ptr::dangling`] has id
+    /// `0`.
+    ///
+    /// Without the `std` feature, the allocations of all threads are counted together.
+    pub fn id(&self) -> u64 {
+        self.alloc.id
+    }
+
+    /// Returns a pointer that is always stale, e.g. as a placeholder for a field whose target
+    /// isn't allocated yet.
+    ///
+    /// ```
+    /// let ptr = genalloc::Ptr::<u32>::dangling();
+    /// assert!(!ptr.is_valid());
+    /// assert!(matches!(ptr.try_read(), Err(genalloc::AccessError::Stale(err)) if err.is_dangling()));
+    /// ```
+    pub const fn dangling() -> Self {
+        Self {
+            alloc: Alloc {
+                slot: &DANGLING_SLOT.0,
+                gen: RETIRED_GEN,
+                id: 0,
+                #[cfg(feature = "debug-spans")]
+                span: None,
+            },
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T: ?Sized + Pointee> Ptr<T> {
+    /// Returns `true` if the [`Span`] owning the allocation is still alive.
+    ///
+    /// This never borrows the pointed-to value, so it can be called while a guard returned by
+    /// [`Ptr::read`] or [`Ptr::write`] is alive.
+    pub fn is_valid(&self) -> bool {
+        self.alloc.check().is_ok()
+    }
+
+    /// Returns the generation this pointer was created with.
+    pub fn generation(&self) -> u32 {
+        self.alloc.gen
+    }
+
+    /// Returns an adapter formatting the pointed-to value with [`fmt::Debug`].
+    ///
+    /// Formats `<stale>` or `<borrowed>` instead of panicking when the value can't be read.
+    pub fn debug_value(&self) -> DebugValue<'_, T>
+    where
+        T: fmt::Debug,
+    {
+        DebugValue(self)
+    }
+
+    /// Erases the pointer's type, see [`ErasedPtr`].
+    pub fn erase(self) -> ErasedPtr {
+        ErasedPtr::from(self)
+    }
+
+    /// Creates a [`WeakPtr`] to the same allocation.
+    pub fn downgrade(&self) -> WeakPtr<T> {
+        WeakPtr {
+            alloc: self.alloc,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Creates a [`PtrToken`] that can be sent to other threads and turned back into this
+    /// pointer on the current thread.
+    ///
+    /// Requires the `std` feature.
+    #[cfg(feature = "std")]
+    pub fn to_token(&self) -> PtrToken<T> {
+        PtrToken {
+            alloc: self.alloc,
+            thread: std::thread::current().id(),
+            _marker: PhantomData,
+        }
+    }
+
+    /// Flattens this pointer into the address of its allocation, its generation and its
+    /// [`Ptr::id`], e.g. to store it in a packet or hand it to C code, see
+    /// [`Ptr::from_raw_parts`].
+    pub fn into_raw_parts(self) -> (usize, u32, u64) {
+        (
+            core::ptr::from_ref(self.alloc.slot) as usize,
+            self.alloc.gen,
+            self.alloc.id,
+        )
+    }
+
+    /// Rebuilds a pointer from the parts returned by [`Ptr::into_raw_parts`].
+    ///
+    /// Rebuilding it with another id changes how it's ordered, not what it points to. The pointer
+    /// is stale if the allocation was recycled in the meantime. A `T` other than the
+    /// stored type is not unsound, accesses then fail with [`AccessError::TypeMismatch`].
+    ///
+    /// # Safety
+    /// `addr` must come from [`Ptr::into_raw_parts`] on the current thread, and the allocation
+    /// must not have been freed since, see [`PoolStorage::drain`].
+    pub unsafe fn from_raw_parts(addr: usize, gen: u32, id: u64) -> Self {
+        Self {
+            alloc: Alloc {
+                // SAFETY: The caller guarantees that `addr` points to a leaked slot of this
+                // thread.
+                slot: unsafe { &*(addr as *const Slot) },
+                gen,
+                id,
+                #[cfg(feature = "debug-spans")]
+                span: None,
+            },
+            _marker: PhantomData,
+        }
+    }
+
+    /// Encodes this pointer as a [`RawHandle`], see [`Ptr::into_raw_parts`].
+    pub fn into_raw(self) -> RawHandle {
+        let (slot, gen, id) = self.into_raw_parts();
+        RawHandle { slot, gen, id }
+    }
+
+    /// Rebuilds a pointer from a [`RawHandle`] returned by [`Ptr::into_raw`].
+    ///
+    /// # Safety
+    /// Same as [`Ptr::from_raw_parts`], in particular the handle must come from the current
+    /// thread of the same process run.
+    pub unsafe fn from_raw(raw: RawHandle) -> Self {
+        // SAFETY: Guaranteed by the caller.
+        unsafe { Self::from_raw_parts(raw.slot, raw.gen, raw.id) }
+    }
+
+    /// Pins the allocation, deferring its recycling until the returned [`KeepAlive`] drops.
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale.
+    /// See [`Ptr::try_keep_alive`] for a non-panicking variant.
+    pub fn keep_alive(&self) -> KeepAlive<T> {
+        match self.try_keep_alive() {
+            Ok(keep_alive) => keep_alive,
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Pins the allocation, returning an error if the pointer is stale, see [`Ptr::keep_alive`].
+    pub fn try_keep_alive(&self) -> Result<KeepAlive<T>, AccessError> {
+        self.alloc.check()?;
+        let keep_alive = &self.alloc.slot.keep_alive;
+        keep_alive.set(keep_alive.get() + 1);
+        Ok(KeepAlive { ptr: *self })
+    }
+
+    /// Returns `true` if both pointers point to the same generation of the same allocation,
+    /// regardless of their types.
+    ///
+    /// The pointed-to values are never compared, and a stale pointer never equals a pointer to a
+    /// value that reused its allocation.
+    pub fn ptr_eq<U: ?Sized>(this: &Self, other: &Ptr<U>) -> bool {
+        this.alloc == other.alloc
+    }
+
+    /// Immutably borrows the pointed-to value.
+    ///
+    /// Prefer [`Ptr::with`] where possible, a guard stashed away by accident leads to borrow
+    /// conflicts in unrelated code.
+    /// The returned guard may outlive the [`Span`]. The pointer becomes stale as usual, but the
+    /// allocation isn't reused and its value isn't dropped until every guard to it is released.
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale or the value is currently mutably borrowed.
+    /// See [`Ptr::try_read`] for a non-panicking variant.
+    pub fn read(&self) -> Ref<'static, T> {
+        match self.try_read() {
+            Ok(borrow) => borrow,
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Mutably borrows the pointed-to value.
+    ///
+    /// Prefer [`Ptr::with_mut`] where possible, see [`Ptr::read`].
+    /// If a panic unwinds while the returned guard is held, the allocation gets poisoned, see
+    /// [`AccessError::Poisoned`].
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale, poisoned or the value is currently borrowed.
+    /// See [`Ptr::try_write`] for a non-panicking variant.
+    pub fn write(&self) -> PtrRefMut<T> {
+        match self.try_write() {
+            Ok(borrow) => borrow,
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Panics with `err` and the pointer's type.
+    ///
+    /// Stale pointers also tell whether their allocation is empty, reused or still borrowed,
+    /// unless the `shared-pool` feature is enabled. With the `debug-tracking` feature, the origin
+    /// of the allocation is included if it's tracked.
+    fn fail(&self, err: AccessError) -> ! {
+        // With the `shared-pool` feature a stale allocation may be in use on another thread, so
+        // only its generation may be read.
+        let context = match err {
+            AccessError::Stale(err) if cfg!(feature = "shared-pool") || err.is_dangling() => "",
+            AccessError::Stale(_) => match self.alloc.slot.value.try_borrow().map(|v| v.is_empty())
+            {
+                Ok(true) => STALE_EMPTY,
+                Ok(false) if self.alloc.slot.is_deferred() => STALE_DEFERRED,
+                Ok(false) => STALE_REUSED,
+                Err(_) => "",
+            },
+            _ => "",
+        };
+        #[cfg(feature = "debug-tracking")]
+        if let Some(message) = tracking::describe(self.alloc, &format_args!("{err}{context}")) {
+            panic!("Ptr<{}> {message}", core::any::type_name::<T>());
+        }
+        err.panic::<T>("Ptr", context)
+    }
+
+    /// Immutably borrows the pointed-to value, returning [`None`] if it's mutably borrowed through
+    /// another copy of the pointer.
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale, like [`Ptr::read`].
+    /// See [`Ptr::try_read`] to also handle stale pointers without panicking.
+    pub fn checked_read(&self) -> Option<Ref<'static, T>> {
+        match self.try_read() {
+            Ok(borrow) => Some(borrow),
+            Err(AccessError::AlreadyBorrowed) => None,
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Mutably borrows the pointed-to value, returning [`None`] if it's borrowed through another
+    /// copy of the pointer.
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale, like [`Ptr::write`].
+    /// See [`Ptr::try_write`] to also handle stale pointers without panicking.
+    pub fn checked_write(&self) -> Option<PtrRefMut<T>> {
+        match self.try_write() {
+            Ok(borrow) => Some(borrow),
+            Err(AccessError::AlreadyBorrowed) => None,
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Immutably borrows the pointed-to value, returning an error if the borrow is not possible.
+    pub fn try_read(&self) -> Result<Ref<'static, T>, AccessError> {
+        self.alloc.check()?;
+        if self.alloc.slot.poisoned.get() {
+            return Err(AccessError::Poisoned);
+        }
+        let borrow = self
+            .alloc
+            .slot
+            .value
+            .try_borrow()
+            .map_err(|_| AccessError::AlreadyBorrowed)?;
+        Ref::filter_map(borrow, |any| T::from_any(any.as_deref()?))
+            .map_err(|borrow| AccessError::type_mismatch::<T>(borrow.as_deref()))
+    }
+
+    /// Mutably borrows the pointed-to value, returning an error if the borrow is not possible.
+    pub fn try_write(&self) -> Result<PtrRefMut<T>, AccessError> {
+        if self.alloc.check().is_ok() && self.alloc.slot.pinned.get() {
+            return Err(AccessError::Pinned);
+        }
+        self.try_write_unpinned()
+    }
+
+    /// Immutably borrows the pinned value of an allocation made by [`Span::alloc_pinned`].
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale, the value is currently mutably borrowed, or the
+    /// allocation wasn't made by [`Span::alloc_pinned`].
+    /// See [`Ptr::try_read_pinned`] for a non-panicking variant.
+    pub fn read_pinned(&self) -> Pin<Ref<'static, T>> {
+        match self.try_read_pinned() {
+            Ok(borrow) => borrow,
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Mutably borrows the pinned value of an allocation made by [`Span::alloc_pinned`].
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale, the value is currently borrowed, or the allocation wasn't
+    /// made by [`Span::alloc_pinned`].
+    /// See [`Ptr::try_write_pinned`] for a non-panicking variant.
+    pub fn write_pinned(&self) -> Pin<PtrRefMut<T>> {
+        match self.try_write_pinned() {
+            Ok(borrow) => borrow,
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Immutably borrows the pinned value, returning an error if the borrow is not possible, see
+    /// [`Ptr::read_pinned`].
+    pub fn try_read_pinned(&self) -> Result<Pin<Ref<'static, T>>, AccessError> {
+        self.check_pinned()?;
+        // SAFETY: Pinned values are boxed and every access that could move them fails, see
+        // `Span::alloc_pinned`.
+        Ok(unsafe { Pin::new_unchecked(self.try_read()?) })
+    }
+
+    /// Mutably borrows the pinned value, returning an error if the borrow is not possible, see
+    /// [`Ptr::write_pinned`].
+    pub fn try_write_pinned(&self) -> Result<Pin<PtrRefMut<T>>, AccessError> {
+        self.check_pinned()?;
+        // SAFETY: As above.
+        Ok(unsafe { Pin::new_unchecked(self.try_write_unpinned()?) })
+    }
+
+    fn check_pinned(&self) -> Result<(), AccessError> {
+        self.alloc.check()?;
+        if self.alloc.slot.pinned.get() {
+            Ok(())
+        } else {
+            Err(AccessError::NotPinned)
+        }
+    }
+
+    fn try_write_unpinned(&self) -> Result<PtrRefMut<T>, AccessError> {
+        self.alloc.check()?;
+        if self.alloc.slot.poisoned.get() {
+            return Err(AccessError::Poisoned);
+        }
+        let borrow = self
+            .alloc
+            .slot
+            .value
+            .try_borrow_mut()
+            .map_err(|_| AccessError::AlreadyBorrowed)?;
+        let borrow = RefMut::filter_map(borrow, |any| T::from_any_mut(any.as_deref_mut()?))
+            .map_err(|borrow| AccessError::type_mismatch::<T>(borrow.as_deref()))?;
+        Ok(PtrRefMut {
+            borrow,
+            poison: Poison::new(self.alloc.slot),
+        })
+    }
+
+    /// Returns a raw pointer to the pointed-to value, e.g. for FFI.
+    ///
+    /// The value never moves while it's borrowed and when it's overwritten through
+    /// [`Ptr::write`], so the address stays the same across accesses until the value is moved
+    /// out or replaced, e.g. by [`Ptr::take`], [`Ptr::swap`] or by recycling the allocation.
+    ///
+    /// Dereferencing the pointer is `unsafe`. Borrowing the value again, e.g. by [`Ptr::read`],
+    /// [`Ptr::write`] or this function, ends the validity of the pointer, as the borrow must not
+    /// be aliased. Returning to raw accesses afterwards needs a new pointer, which has the same
+    /// address.
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale or the value is currently mutably borrowed, like
+    /// [`Ptr::read`].
+    pub fn as_ptr(&self) -> *const T {
+        core::ptr::from_ref(&*self.read())
+    }
+
+    /// Returns a raw pointer to the pointed-to value that may be written through, see
+    /// [`Ptr::as_ptr`].
+    ///
+    /// Also works for pinned values, which must not be moved through the pointer then.
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale or the value is currently borrowed, like [`Ptr::write`].
+    pub fn as_mut_ptr(&self) -> *mut T {
+        match self.try_write_unpinned() {
+            Ok(mut borrow) => core::ptr::from_mut(&mut *borrow),
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Returns `true` if a panic unwound while the value was mutably borrowed, see
+    /// [`AccessError::Poisoned`].
+    pub fn is_poisoned(&self) -> bool {
+        self.alloc.check().is_ok() && self.alloc.slot.poisoned.get()
+    }
+
+    /// Returns how the pointed-to value is currently borrowed, to back off instead of panicking in
+    /// [`Ptr::read`] or [`Ptr::write`].
+    ///
+    /// Never panics and leaves no borrow behind.
+    pub fn borrow_state(&self) -> BorrowState {
+        self.alloc.borrow_state()
+    }
+
+    /// Clears the poisoned state of the allocation, making the value accessible again.
+    pub fn clear_poison(&self) {
+        if self.alloc.check().is_ok() {
+            self.alloc.slot.poisoned.set(false);
+        }
+    }
+
+    /// Swaps the pointed-to values of both pointers.
+    ///
+    /// Values on the heap stay where they are, only their boxes are swapped, while values stored
+    /// inline are moved. Does nothing if both pointers point to the same allocation.
+    ///
+    /// # Panics
+    /// Panics if either pointer is stale or its value is currently borrowed.
+    /// See [`Ptr::try_swap`] for a non-panicking variant.
+    pub fn swap(&self, other: Ptr<T>) {
+        if let Err((ptr, err)) = self.swap_impl(other) {
+            ptr.fail(err);
+        }
+    }
+
+    /// Swaps the pointed-to values of both pointers, returning an error if either can't be
+    /// mutably borrowed, see [`Ptr::swap`].
+    pub fn try_swap(&self, other: Ptr<T>) -> Result<(), AccessError> {
+        self.swap_impl(other).map_err(|(_, err)| err)
+    }
+
+    /// Swaps the pointed-to values, returning the error along with the pointer that caused it.
+    fn swap_impl(&self, other: Ptr<T>) -> Result<(), (Ptr<T>, AccessError)> {
+        let borrow = self.try_write().map_err(|err| (*self, err))?;
+        if self.alloc == other.alloc {
+            return Ok(());
+        }
+        let other_borrow = other.try_write().map_err(|err| (other, err))?;
+        drop((borrow, other_borrow));
+        // Both allocations hold a `T`, so swapping their values' storage swaps the values.
+        self.alloc.slot.value.swap(&other.alloc.slot.value);
+        Ok(())
+    }
+
+    /// Immutably borrows the pointed-to value for the duration of `f`.
+    ///
+    /// The borrow is released before returning, even if `f` panics, so unlike with
+    /// [`Ptr::read`] no guard can escape.
+    /// ```
+    /// # #[cfg(feature = "std")] {
+    /// let mut span = genalloc::Span::new();
+    /// let name = span.alloc("player".to_string());
+    /// assert_eq!(name.with(|name| name.len()), 6);
+    /// name.with_mut(|name| name.push('1'));
+    /// # }
+    /// ```
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale or the value is currently mutably borrowed.
+    /// See [`Ptr::try_with`] for a non-panicking variant.
+    pub fn with<R>(&self, f: impl FnOnce(&T) -> R) -> R {
+        f(&*self.read())
+    }
+
+    /// Immutably borrows the pointed-to value for the duration of `f`, returning an error if the
+    /// borrow is not possible.
+    pub fn try_with<R>(&self, f: impl FnOnce(&T) -> R) -> Result<R, AccessError> {
+        Ok(f(&*self.try_read()?))
+    }
+
+    /// Mutably borrows the pointed-to value for the duration of `f`.
+    ///
+    /// The borrow is released before returning, even if `f` panics.
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale or the value is currently borrowed.
+    /// See [`Ptr::try_with_mut`] for a non-panicking variant.
+    pub fn with_mut<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        f(&mut *self.write())
+    }
+
+    /// Mutably borrows the pointed-to value for the duration of `f`, returning an error if the
+    /// borrow is not possible.
+    pub fn try_with_mut<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, AccessError> {
+        Ok(f(&mut *self.try_write()?))
+    }
+
+    /// Projects the pointer to a part of the pointed-to value, e.g. one of its fields.
+    ///
+    /// The returned [`ProjectedPtr`] is valid exactly as long as this pointer.
+    /// ```
+    /// struct Player {
+    ///     health: u32,
+    /// }
+    ///
+    /// # #[cfg(feature = "std")] {
+    /// let mut span = genalloc::Span::new();
+    /// let player = span.alloc(Player { health: 100 });
+    /// let health = player.map(|p| &p.health, |p| &mut p.health);
+    /// *health.write() -= 10;
+    /// assert_eq!(player.read().health, 90);
+    /// # }
+    /// ```
+    pub fn map<U: ?Sized + 'static>(
+        self,
+        f: fn(&T) -> &U,
+        g: fn(&mut T) -> &mut U,
+    ) -> ProjectedPtr<Self, U> {
+        ProjectedPtr {
+            parent: self,
+            get: f,
+            get_mut: g,
+        }
+    }
+
+    /// Same as [`Ptr::with_mut`].
+    pub fn update<R>(&self, f: impl FnOnce(&mut T) -> R) -> R {
+        self.with_mut(f)
+    }
+
+    /// Same as [`Ptr::try_with_mut`].
+    pub fn try_update<R>(&self, f: impl FnOnce(&mut T) -> R) -> Result<R, AccessError> {
+        self.try_with_mut(f)
+    }
+}
+
+impl<T: 'static> Ptr<T> {
+    /// Overwrites the pointed-to value, dropping the previous one.
+    ///
+    /// The new value is written into the existing heap allocation, as are the ones of
+    /// [`Ptr::replace`] and the `try_` variants.
+    /// Also refills allocations emptied by [`Ptr::take_value`].
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale or the value is currently borrowed.
+    /// See [`Ptr::try_set`] for a non-panicking variant.
+    pub fn set(&self, v: T) {
+        if let Err(err) = self.try_set(v) {
+            self.fail(err);
+        }
+    }
+
+    /// Overwrites the pointed-to value, dropping the previous one, see [`Ptr::set`].
+    ///
+    /// On failure `v` is dropped.
+    pub fn try_set(&self, v: T) -> Result<(), AccessError> {
+        match self.try_write() {
+            Ok(mut value) => *value = v,
+            Err(AccessError::TypeMismatch { actual: None, .. }) => {
+                *self.alloc.slot.value.borrow_mut() = self.alloc.slot.boxed(v);
+            }
+            Err(err) => return Err(err),
+        }
+        Ok(())
+    }
+
+    /// Replaces the pointed-to value, returning the previous one.
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale or the value is currently borrowed.
+    /// See [`Ptr::try_replace`] for a non-panicking variant.
+    pub fn replace(&self, v: T) -> T {
+        core::mem::replace(&mut *self.write(), v)
+    }
+
+    /// Replaces the pointed-to value, returning the previous one.
+    ///
+    /// On failure `v` is dropped.
+    pub fn try_replace(&self, v: T) -> Result<T, AccessError> {
+        Ok(core::mem::replace(&mut *self.try_write()?, v))
+    }
+
+    /// Returns a clone of the pointed-to value, only borrowing it while cloning.
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale or the value is currently mutably borrowed.
+    /// See [`Ptr::try_get_cloned`] for a non-panicking variant.
+    #[doc(alias = "cloned")]
+    pub fn get_cloned(&self) -> T
+    where
+        T: Clone,
+    {
+        self.read().clone()
+    }
+
+    /// Returns a clone of the pointed-to value, returning an error if the borrow is not possible.
+    pub fn try_get_cloned(&self) -> Result<T, AccessError>
+    where
+        T: Clone,
+    {
+        Ok(self.try_read()?.clone())
+    }
+
+    /// Returns a copy of the pointed-to value, only borrowing it while copying.
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale or the value is currently mutably borrowed.
+    /// See [`Ptr::try_get`] for a non-panicking variant.
+    #[doc(alias = "copied")]
+    pub fn get(&self) -> T
+    where
+        T: Copy,
+    {
+        *self.read()
+    }
+
+    /// Returns a copy of the pointed-to value, returning an error if the borrow is not possible.
+    pub fn try_get(&self) -> Result<T, AccessError>
+    where
+        T: Copy,
+    {
+        Ok(*self.try_read()?)
+    }
+
+    /// Moves the value out, leaving the allocation empty but live.
+    ///
+    /// Unlike [`Ptr::take`] this doesn't change the generation, so the pointer and its copies
+    /// stay valid and can be refilled with [`Ptr::set`]. Until then, reading the value fails
+    /// with [`AccessError::TypeMismatch`] and no `actual` type, and [`Ptr::read`] panics.
+    ///
+    /// Returns [`None`] if the pointer is stale, its value is currently borrowed, or the
+    /// allocation doesn't hold a `T`.
+    pub fn take_value(&self) -> Option<T> {
+        self.try_write().ok()?;
+        let value = self.alloc.slot.value.take();
+        Some(
+            value
+                .downcast()
+                .ok()
+                .expect("value was checked to be of type `T`"),
+        )
+    }
+
+    /// Moves the value out and recycles the allocation without waiting for the [`Span`] to drop.
+    ///
+    /// All copies of this pointer become stale.
+    /// Fails with [`AccessError::AlreadyBorrowed`] while the allocation is pinned by a
+    /// [`KeepAlive`].
+    pub fn take(&self) -> Result<T, AccessError> {
+        self.try_write()?;
+        if self.alloc.slot.keep_alive.get() > 0 {
+            return Err(AccessError::AlreadyBorrowed);
+        }
+        let value = self.alloc.recycle();
+        Ok(value
+            .downcast()
+            .ok()
+            .expect("value was checked to be of type `T`"))
+    }
+}
+
+/// Pointers that can be projected with [`Ptr::map`] and [`ProjectedPtr::map`].
+pub trait Project: Copy {
+    type Target: ?Sized + 'static;
+
+    /// Returns `true` if the [`Span`] owning the allocation is still alive.
+    fn is_valid(&self) -> bool;
+
+    /// Immutably borrows the pointed-to value, returning an error if the borrow is not possible.
+    fn try_read(&self) -> Result<Ref<'static, Self::Target>, AccessError>;
+
+    /// Mutably borrows the pointed-to value, returning an error if the borrow is not possible.
+    fn try_write(&self) -> Result<PtrRefMut<Self::Target>, AccessError>;
+}
+
+impl<T: ?Sized + Pointee> Project for Ptr<T> {
+    type Target = T;
+
+    fn is_valid(&self) -> bool {
+        Ptr::is_valid(self)
+    }
+
+    fn try_read(&self) -> Result<Ref<'static, T>, AccessError> {
+        Ptr::try_read(self)
+    }
+
+    fn try_write(&self) -> Result<PtrRefMut<T>, AccessError> {
+        Ptr::try_write(self)
+    }
+}
+
+/// Generational pointer to a part of a value, see [`Ptr::map`].
+///
+/// `P` is the pointer it was projected from.
+pub struct ProjectedPtr<P: Project, U: ?Sized + 'static> {
+    parent: P,
+    get: fn(&P::Target) -> &U,
+    get_mut: fn(&mut P::Target) -> &mut U,
+}
+
+impl<P: Project, U: ?Sized + 'static> Clone for ProjectedPtr<P, U> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<P: Project, U: ?Sized + 'static> Copy for ProjectedPtr<P, U> {}
+
+impl<P: Project, U: ?Sized + 'static> ProjectedPtr<P, U> {
+    /// Returns `true` if the [`Span`] owning the allocation is still alive.
+    pub fn is_valid(&self) -> bool {
+        self.parent.is_valid()
+    }
+
+    /// Immutably borrows the projected value.
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale or the value is currently mutably borrowed.
+    /// See [`ProjectedPtr::try_read`] for a non-panicking variant.
+    pub fn read(&self) -> Ref<'static, U> {
+        match self.try_read() {
+            Ok(borrow) => borrow,
+            Err(err) => err.panic::<U>("ProjectedPtr", ""),
+        }
+    }
+
+    /// Mutably borrows the projected value.
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale or the value is currently borrowed.
+    /// See [`ProjectedPtr::try_write`] for a non-panicking variant.
+    pub fn write(&self) -> PtrRefMut<U> {
+        match self.try_write() {
+            Ok(borrow) => borrow,
+            Err(err) => err.panic::<U>("ProjectedPtr", ""),
+        }
+    }
+
+    /// Immutably borrows the projected value, returning an error if the borrow is not possible.
+    pub fn try_read(&self) -> Result<Ref<'static, U>, AccessError> {
+        Ok(Ref::map(self.parent.try_read()?, self.get))
+    }
+
+    /// Mutably borrows the projected value, returning an error if the borrow is not possible.
+    pub fn try_write(&self) -> Result<PtrRefMut<U>, AccessError> {
+        Ok(PtrRefMut::map(self.parent.try_write()?, self.get_mut))
+    }
+
+    /// Projects the pointer further, see [`Ptr::map`].
+    pub fn map<V: ?Sized + 'static>(
+        self,
+        f: fn(&U) -> &V,
+        g: fn(&mut U) -> &mut V,
+    ) -> ProjectedPtr<Self, V> {
+        ProjectedPtr {
+            parent: self,
+            get: f,
+            get_mut: g,
+        }
+    }
+}
+
+impl<P: Project, U: ?Sized + 'static> Project for ProjectedPtr<P, U> {
+    type Target = U;
+
+    fn is_valid(&self) -> bool {
+        ProjectedPtr::is_valid(self)
+    }
+
+    fn try_read(&self) -> Result<Ref<'static, U>, AccessError> {
+        ProjectedPtr::try_read(self)
+    }
+
+    fn try_write(&self) -> Result<PtrRefMut<U>, AccessError> {
+        ProjectedPtr::try_write(self)
+    }
+}
+
+/// Exclusive borrow guard returned by [`Ptr::write`].
+///
+/// Poisons the allocation if it's dropped while a panic unwinds, which requires the `std`
+/// feature.
+pub struct PtrRefMut<T: ?Sized + 'static> {
+    borrow: RefMut<'static, T>,
+    poison: Poison,
+}
+
+impl<T: ?Sized + 'static> PtrRefMut<T> {
+    /// Makes a guard for a part of the borrowed value, like [`RefMut::map`].
+    pub fn map<U: ?Sized + 'static>(this: Self, f: impl FnOnce(&mut T) -> &mut U) -> PtrRefMut<U> {
+        PtrRefMut {
+            borrow: RefMut::map(this.borrow, f),
+            poison: this.poison,
+        }
+    }
+}
+
+impl<T: ?Sized + 'static> core::ops::Deref for PtrRefMut<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        &self.borrow
+    }
+}
+
+impl<T: ?Sized + 'static> core::ops::DerefMut for PtrRefMut<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        &mut self.borrow
+    }
+}
+
+impl<T: ?Sized + fmt::Debug + 'static> fmt::Debug for PtrRefMut<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.borrow.fmt(f)
+    }
+}
+
+/// Poisons a slot when dropped by a panic that started after its creation.
+struct Poison {
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    slot: &'static Slot,
+    #[cfg(feature = "std")]
+    panicking: bool,
+}
+
+impl Poison {
+    fn new(slot: &'static Slot) -> Self {
+        Self {
+            slot,
+            #[cfg(feature = "std")]
+            panicking: std::thread::panicking(),
+        }
+    }
+}
+
+impl Drop for Poison {
+    fn drop(&mut self) {
+        #[cfg(feature = "std")]
+        if !self.panicking && std::thread::panicking() {
+            self.slot.poisoned.set(true);
+        }
+    }
+}
+
+/// Pin keeping an allocation alive, see [`Ptr::keep_alive`].
+///
+/// While any [`KeepAlive`] for an allocation exists, clearing or dropping its [`Span`] leaves the
+/// allocation and all pointers to it valid. The allocation gets recycled once the last
+/// [`KeepAlive`] drops instead.
+///
+/// Dereferences to the pinned [`Ptr`].
+pub struct KeepAlive<T: ?Sized> {
+    ptr: Ptr<T>,
+}
+
+impl<T: ?Sized> core::ops::Deref for KeepAlive<T> {
+    type Target = Ptr<T>;
+
+    fn deref(&self) -> &Ptr<T> {
+        &self.ptr
+    }
+}
+
+impl<T: ?Sized> Drop for KeepAlive<T> {
+    fn drop(&mut self) {
+        let slot = self.ptr.alloc.slot;
+        slot.keep_alive.set(slot.keep_alive.get() - 1);
+        if slot.keep_alive.get() == 0 && slot.recycle_pending.take() {
+            self.ptr.alloc.release();
+        }
+    }
+}
+
+/// Allocation that's recycled when dropped, see [`Span::alloc_owned`].
+///
+/// Dereferences to a [`Ptr`] to the allocation, which goes stale once the [`Owned`] drops.
+pub struct Owned<T: ?Sized> {
+    ptr: Ptr<T>,
+}
+
+impl<T: ?Sized> Owned<T> {
+    /// Leaves the allocation to its [`Span`], which recycles it when cleared or dropped.
+    pub fn into_ptr(self) -> Ptr<T> {
+        let ptr = self.ptr;
+        core::mem::forget(self);
+        ptr
+    }
+}
+
+impl<T: ?Sized> core::ops::Deref for Owned<T> {
+    type Target = Ptr<T>;
+
+    fn deref(&self) -> &Ptr<T> {
+        &self.ptr
+    }
+}
+
+impl<T: ?Sized> fmt::Debug for Owned<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Owned").field(&self.ptr).finish()
+    }
+}
+
+impl<T: ?Sized> Drop for Owned<T> {
+    fn drop(&mut self) {
+        self.ptr.alloc.release();
+    }
+}
+
+/// Plain-integer encoding of a [`Ptr`] for C callbacks and the like, see [`Ptr::into_raw`].
+///
+/// `slot` is the address of the allocation, so a handle is only meaningful within the process
+/// run and on the thread that created it.
+#[repr(C)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct RawHandle {
+    /// Address of the allocation.
+    pub slot: usize,
+    /// Generation of the pointer, see [`Ptr::generation`].
+    pub gen: u32,
+    /// Id of the pointer, see [`Ptr::id`].
+    pub id: u64,
+}
+
+/// Claim on an allocation that can cross threads, see [`Ptr::to_token`].
+///
+/// The token gives no access to the value, it has to be redeemed for a [`Ptr`] on the thread
+/// that created it first:
+/// ```
+/// let mut span = genalloc::Span::new();
+/// let ptr = span.alloc(5);
+/// let token = ptr.to_token();
+/// let token = std::thread::spawn(move || {
+///     assert!(token.redeem().is_err());
+///     token
+/// })
+/// .join()
+/// .unwrap();
+/// assert_eq!(*token.redeem().unwrap().read(), 5);
+/// ```
+///
+/// Requires the `std` feature.
+#[cfg(feature = "std")]
+pub struct PtrToken<T: ?Sized> {
+    alloc: Alloc,
+    thread: std::thread::ThreadId,
+    _marker: PhantomData<fn() -> *const T>,
+}
+
+#[cfg(feature = "std")]
+impl<T: ?Sized> Clone for PtrToken<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: ?Sized> Copy for PtrToken<T> {}
+
+// SAFETY: The allocation is only accessed by `PtrToken::redeem` on the thread that created the
+// token.
+#[cfg(feature = "std")]
+unsafe impl<T: ?Sized> Send for PtrToken<T> {}
+
+// SAFETY: The token gives no access to the allocation through a shared reference.
+#[cfg(feature = "std")]
+unsafe impl<T: ?Sized> Sync for PtrToken<T> {}
+
+#[cfg(feature = "std")]
+impl<T: ?Sized> PtrToken<T> {
+    /// Turns the token back into a [`Ptr`].
+    ///
+    /// Fails on other threads than the one that created the token, and if the pointer is stale.
+    pub fn redeem(self) -> Result<Ptr<T>, RedeemError> {
+        if std::thread::current().id() != self.thread {
+            return Err(RedeemError::WrongThread);
+        }
+        self.alloc.check()?;
+        Ok(Ptr {
+            alloc: self.alloc,
+            _marker: PhantomData,
+        })
+    }
+}
+
+#[cfg(feature = "std")]
+impl<T: ?Sized> fmt::Debug for PtrToken<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PtrToken")
+            .field("gen", &self.alloc.gen)
+            .field("thread", &self.thread)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Generational pointer that has to be upgraded to a [`Ptr`] before use.
+///
+/// Unlike a [`Ptr`], it makes the possibility of the allocation being recycled explicit.
+pub struct WeakPtr<T: ?Sized> {
+    alloc: Alloc,
+    _marker: PhantomData<T>,
+}
+
+impl<T: ?Sized> Clone for WeakPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized> Copy for WeakPtr<T> {}
+
+impl<T: ?Sized> WeakPtr<T> {
+    /// Returns the [`Ptr`] if the [`Span`] owning the allocation is still alive.
+    pub fn upgrade(&self) -> Option<Ptr<T>> {
+        self.alloc.check().ok()?;
+        Some(Ptr {
+            alloc: self.alloc,
+            _marker: PhantomData,
+        })
+    }
+}
+
+/// Generational pointer to a value that never moves, see [`Span::alloc_pinned`].
+///
+/// Only gives access to the value through [`Pin`], so there is no way to move it out.
+pub struct PinnedPtr<T: ?Sized> {
+    ptr: Ptr<T>,
+}
+
+impl<T: ?Sized> Clone for PinnedPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T: ?Sized> Copy for PinnedPtr<T> {}
+
+impl<T: ?Sized> fmt::Debug for PinnedPtr<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("PinnedPtr").field(&self.ptr).finish()
+    }
+}
+
+impl<T: ?Sized> PartialEq for PinnedPtr<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.ptr == other.ptr
+    }
+}
+
+impl<T: ?Sized> Eq for PinnedPtr<T> {}
+
+impl<T: ?Sized> Hash for PinnedPtr<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.ptr.hash(state);
+    }
+}
+
+impl<T: ?Sized + Pointee> PinnedPtr<T> {
+    /// Returns `true` if the [`Span`] owning the allocation is still alive.
+    pub fn is_valid(&self) -> bool {
+        self.ptr.is_valid()
+    }
+
+    /// Returns the generation this pointer was created with.
+    pub fn generation(&self) -> u32 {
+        self.ptr.generation()
+    }
+
+    /// Immutably borrows the pinned value, see [`Ptr::read_pinned`].
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale or the value is currently mutably borrowed.
+    pub fn read_pin(&self) -> Pin<Ref<'static, T>> {
+        self.ptr.read_pinned()
+    }
+
+    /// Mutably borrows the pinned value, see [`Ptr::write_pinned`].
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale or the value is currently borrowed.
+    pub fn write_pin(&self) -> Pin<PtrRefMut<T>> {
+        self.ptr.write_pinned()
+    }
+
+    /// Immutably borrows the pinned value, returning an error if the borrow is not possible.
+    pub fn try_read_pin(&self) -> Result<Pin<Ref<'static, T>>, AccessError> {
+        self.ptr.try_read_pinned()
+    }
+
+    /// Mutably borrows the pinned value, returning an error if the borrow is not possible.
+    pub fn try_write_pin(&self) -> Result<Pin<PtrRefMut<T>>, AccessError> {
+        self.ptr.try_write_pinned()
+    }
+
+    /// Returns a plain [`Ptr`] to the allocation, e.g. for [`Span::dealloc`], whose accesses
+    /// that could move the value fail with [`AccessError::Pinned`].
+    pub fn ptr(&self) -> Ptr<T> {
+        self.ptr
+    }
+}
+
+/// Generational pointer with its type erased.
+///
+/// Created from a [`Ptr`] with [`From`] and turned back into one with [`ErasedPtr::downcast`].
+/// Equality and hashing follow the same identity semantics as [`Ptr`].
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct ErasedPtr {
+    alloc: Alloc,
+}
+
+impl ErasedPtr {
+    /// Returns `true` if the [`Span`] owning the allocation is still alive.
+    pub fn is_valid(&self) -> bool {
+        self.alloc.check().is_ok()
+    }
+
+    /// Returns the [`TypeId`] of the stored value.
+    ///
+    /// Returns `None` if the pointer is stale or the value is currently mutably borrowed.
+    /// Values of unsized types are stored as a `Box<T>`, see [`Pointee`].
+    pub fn type_id(&self) -> Option<TypeId> {
+        self.alloc.check().ok()?;
+        let value = self.alloc.slot.value.try_borrow().ok()?;
+        Some(value.as_deref()?.type_id())
+    }
+
+    /// Returns the typed [`Ptr`] if the pointer is live and the stored value is of type `T`.
+    pub fn downcast<T: ?Sized + Pointee>(self) -> Option<Ptr<T>> {
+        (self.type_id()? == T::stored_type_id()).then_some(Ptr {
+            alloc: self.alloc,
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<T: ?Sized> From<Ptr<T>> for ErasedPtr {
+    fn from(ptr: Ptr<T>) -> Self {
+        Self { alloc: ptr.alloc }
+    }
+}
+
+impl fmt::Debug for ErasedPtr {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ErasedPtr")
+            .field("slot", &core::ptr::from_ref(self.alloc.slot))
+            .field("gen", &self.alloc.gen)
+            .field("live", &self.is_valid())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Formats the value pointed to by a [`Ptr`], see [`Ptr::debug_value`].
+pub struct DebugValue<'a, T: ?Sized>(&'a Ptr<T>);
+
+impl<T: ?Sized + fmt::Debug + Pointee> fmt::Debug for DebugValue<'_, T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.0.try_read() {
+            Ok(value) => value.fmt(f),
+            Err(AccessError::Stale(_)) => f.write_str("<stale>"),
+            Err(AccessError::AlreadyBorrowed) => f.write_str("<borrowed>"),
+            Err(AccessError::TypeMismatch { .. }) => f.write_str("<type mismatch>"),
+            Err(AccessError::Poisoned) => f.write_str("<poisoned>"),
+            Err(AccessError::Pinned | AccessError::NotPinned) => {
+                unreachable!("values are readable")
+            }
+        }
+    }
+}
+
+/// Types that can be pointed to by a [`Ptr`].
+///
+/// Implemented for all sized types, [`str`] and slices.
+/// Other unsized types like trait objects can implement it with [`pointee!`].
+pub trait Pointee: 'static {
+    #[doc(hidden)]
+    fn stored_type_id() -> TypeId;
+    #[doc(hidden)]
+    fn into_any(value: Box<Self>) -> Box<dyn Any>;
+    #[doc(hidden)]
+    fn from_any(any: &dyn Any) -> Option<&Self>;
+    #[doc(hidden)]
+    fn from_any_mut(any: &mut dyn Any) -> Option<&mut Self>;
+}
+
+impl<T: 'static> Pointee for T {
+    fn stored_type_id() -> TypeId {
+        TypeId::of::<T>()
+    }
+
+    fn into_any(value: Box<Self>) -> Box<dyn Any> {
+        value
+    }
+
+    fn from_any(any: &dyn Any) -> Option<&Self> {
+        any.downcast_ref()
+    }
+
+    fn from_any_mut(any: &mut dyn Any) -> Option<&mut Self> {
+        any.downcast_mut()
+    }
+}
+
+/// Implements [`Pointee`] for unsized types, e.g. trait objects.
+///
+/// Values of such types are stored behind an additional [`Box`].
+/// ```
+/// trait Component {}
+///
+/// genalloc::pointee!(dyn Component);
+/// ```
+#[macro_export]
+macro_rules! pointee {
+    ($($ty:ty),+ $(,)?) => {
+        $(impl $crate::Pointee for $ty {
+            fn stored_type_id() -> ::core::any::TypeId {
+                ::core::any::TypeId::of::<$crate::__private::Box<Self>>()
+            }
+
+            fn into_any(
+                value: $crate::__private::Box<Self>,
+            ) -> $crate::__private::Box<dyn ::core::any::Any> {
+                $crate::__private::Box::new(value)
+            }
+
+            fn from_any(any: &dyn ::core::any::Any) -> ::core::option::Option<&Self> {
+                ::core::option::Option::Some(
+                    &**any.downcast_ref::<$crate::__private::Box<Self>>()?,
+                )
+            }
+
+            fn from_any_mut(
+                any: &mut dyn ::core::any::Any,
+            ) -> ::core::option::Option<&mut Self> {
+                ::core::option::Option::Some(
+                    &mut **any.downcast_mut::<$crate::__private::Box<Self>>()?,
+                )
+            }
+        })+
+    };
+}
+
+pointee!(str);
+
+impl<T: 'static> Pointee for [T] {
+    fn stored_type_id() -> TypeId {
+        TypeId::of::<Box<Self>>()
+    }
+
+    fn into_any(value: Box<Self>) -> Box<dyn Any> {
+        Box::new(value)
+    }
+
+    fn from_any(any: &dyn Any) -> Option<&Self> {
+        Some(&**any.downcast_ref::<Box<Self>>()?)
+    }
+
+    fn from_any_mut(any: &mut dyn Any) -> Option<&mut Self> {
+        Some(&mut **any.downcast_mut::<Box<Self>>()?)
+    }
+}
+
+/// Borrow state of the value behind a [`Ptr`], see [`Ptr::borrow_state`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BorrowState {
+    /// The value can be borrowed both immutably and mutably.
+    Unborrowed,
+    /// The value is immutably borrowed, so only [`Ptr::read`] succeeds.
+    Shared,
+    /// The value is mutably borrowed, so neither [`Ptr::read`] nor [`Ptr::write`] succeed.
+    Exclusive,
+    /// The [`Span`] owning the allocation was dropped.
+    Dangling,
+}
+
+/// Error returned when a [`Ptr`] can't be dereferenced.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccessError {
+    /// The [`Span`] owning the allocation was dropped.
+    Stale(DanglingError),
+    /// The value is already borrowed in a way that conflicts with the requested borrow.
+    AlreadyBorrowed,
+    /// The allocation doesn't hold a value of the pointer's type.
+    TypeMismatch {
+        /// Type of values stored for the pointer's type.
+        expected: TypeId,
+        /// Type of the stored value, `None` if the allocation is empty.
+        actual: Option<TypeId>,
+    },
+    /// A panic unwound while the value was mutably borrowed, so it may be left in an
+    /// inconsistent state, see [`Ptr::clear_poison`].
+    Poisoned,
+    /// The value is pinned, so it can only be mutably borrowed through [`Ptr::write_pinned`],
+    /// see [`Span::alloc_pinned`].
+    Pinned,
+    /// The allocation wasn't made by [`Span::alloc_pinned`], so its value can't be pinned.
+    NotPinned,
+}
+
+impl fmt::Display for AccessError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Stale(err) => err.fmt(f),
+            Self::AlreadyBorrowed => f.write_str("value is already borrowed"),
+            Self::TypeMismatch { actual: None, .. } => f.write_str("allocation is empty"),
+            Self::TypeMismatch { .. } => {
+                f.write_str("allocation holds a value of a different type")
+            }
+            Self::Poisoned => f.write_str("value was poisoned by a panic while mutably borrowed"),
+            Self::Pinned => f.write_str("value is pinned and may only be accessed through `Pin`"),
+            Self::NotPinned => f.write_str("value is not pinned"),
+        }
+    }
+}
+
+/// Context of a stale access panic whose allocation is empty, see [`AccessError::panic`].
+const STALE_EMPTY: &str = " (the allocation was freed, it's empty)";
+/// Context of a stale access panic whose value is still borrowed.
+const STALE_DEFERRED: &str = " (the allocation was freed while the value was borrowed, the value \
+                              is dropped once the borrow is released)";
+/// Context of a stale access panic whose allocation holds a newer value.
+const STALE_REUSED: &str = " (the allocation was freed and reused for another value)";
+
+impl AccessError {
+    /// Panics with this error and `context`, naming the pointer kind `ptr` and its type `T`.
+    #[cold]
+    fn panic<T: ?Sized>(self, ptr: &str, context: &str) -> ! {
+        panic!("{ptr}<{}>: {self}{context}", core::any::type_name::<T>())
+    }
+
+    fn type_mismatch<T: ?Sized + Pointee>(actual: Option<&dyn Any>) -> Self {
+        Self::TypeMismatch {
+            expected: T::stored_type_id(),
+            actual: actual.map(|any| any.type_id()),
+        }
+    }
+}
+
+impl Error for AccessError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Stale(err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+/// Error returned when a [`Ptr`] outlived the [`Span`] owning its allocation.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct DanglingError {
+    /// Generation the pointer was created with.
+    pub expected: u32,
+    /// Current generation of the allocation.
+    pub actual: u32,
+    #[cfg(feature = "debug-spans")]
+    span: Option<SpanId>,
+    #[cfg(feature = "debug-spans")]
+    recycled_by: Option<SpanId>,
+}
+
+impl DanglingError {
+    /// Returns `true` if the pointer was created by [`Ptr::dangling`], so it never pointed to a
+    /// value.
+    pub fn is_dangling(&self) -> bool {
+        self.expected == RETIRED_GEN
+    }
+
+    /// Returns the [`Span`] that owned the allocation when the pointer was copied, [`None`] if
+    /// it's unknown.
+    ///
+    /// Requires the `debug-spans` feature.
+    #[cfg(feature = "debug-spans")]
+    pub fn span(&self) -> Option<SpanId> {
+        self.span
+    }
+
+    /// Returns the [`Span`] that last recycled the allocation, [`None`] if it's unknown.
+    ///
+    /// Requires the `debug-spans` feature.
+    #[cfg(feature = "debug-spans")]
+    pub fn recycled_by(&self) -> Option<SpanId> {
+        self.recycled_by
+    }
+
+    pub(crate) fn new(expected: u32, actual: u32) -> Self {
+        Self {
+            expected,
+            actual,
+            #[cfg(feature = "debug-spans")]
+            span: None,
+            #[cfg(feature = "debug-spans")]
+            recycled_by: None,
+        }
+    }
+}
+
+impl fmt::Display for DanglingError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.is_dangling() {
+            return f.write_str("dangling pointer, see `Ptr::dangling`");
+        }
+        f.write_str("pointer ")?;
+        #[cfg(feature = "debug-spans")]
+        if let Some(span) = self.span {
+            write!(f, "from {span} ")?;
+        }
+        write!(
+            f,
+            "is stale, expected generation {} but the allocation is at generation {}",
+            self.expected, self.actual
+        )?;
+        #[cfg(feature = "debug-spans")]
+        if let Some(span) = self.recycled_by {
+            write!(f, ", last recycled by {span}")?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for DanglingError {}
+
+impl From<DanglingError> for AccessError {
+    fn from(err: DanglingError) -> Self {
+        Self::Stale(err)
+    }
+}
+
+/// Error returned by [`Span::free`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FreeError {
+    /// The allocation belongs to a different [`Span`].
+    Foreign,
+    /// The allocation can't be accessed.
+    Access(AccessError),
+}
+
+impl fmt::Display for FreeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Foreign => f.write_str("allocation belongs to a different span"),
+            Self::Access(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for FreeError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Foreign => None,
+            Self::Access(err) => Some(err),
+        }
+    }
+}
+
+impl From<AccessError> for FreeError {
+    fn from(err: AccessError) -> Self {
+        Self::Access(err)
+    }
+}
+
+/// Error returned by [`Span::transfer`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferError {
+    /// The allocation belongs to a different [`Span`].
+    Foreign,
+    /// The pointer is stale.
+    Stale(DanglingError),
+}
+
+impl fmt::Display for TransferError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Foreign => f.write_str("allocation belongs to a different span"),
+            Self::Stale(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for TransferError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::Foreign => None,
+            Self::Stale(err) => Some(err),
+        }
+    }
+}
+
+impl From<DanglingError> for TransferError {
+    fn from(err: DanglingError) -> Self {
+        Self::Stale(err)
+    }
+}
+
+/// Error returned by [`PtrToken::redeem`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RedeemError {
+    /// The token was created on a different thread.
+    WrongThread,
+    /// The pointer is stale.
+    Stale(DanglingError),
+}
+
+impl fmt::Display for RedeemError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::WrongThread => f.write_str("token was created on a different thread"),
+            Self::Stale(err) => err.fmt(f),
+        }
+    }
+}
+
+impl Error for RedeemError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            Self::WrongThread => None,
+            Self::Stale(err) => Some(err),
+        }
+    }
+}
+
+impl From<DanglingError> for RedeemError {
+    fn from(err: DanglingError) -> Self {
+        Self::Stale(err)
+    }
+}
+
+/// Identifier of a [`Span`], unique within the program, see [`Span::id`].
+///
+/// Requires the `debug-spans` feature.
+#[cfg(feature = "debug-spans")]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct SpanId(u64);
+
+#[cfg(feature = "debug-spans")]
+impl SpanId {
+    fn next() -> Self {
+        static NEXT: core::sync::atomic::AtomicU64 = core::sync::atomic::AtomicU64::new(0);
+        Self(NEXT.fetch_add(1, core::sync::atomic::Ordering::Relaxed))
+    }
+
+    /// Returns the id as a number, ids are assigned in increasing order.
+    pub fn get(self) -> u64 {
+        self.0
+    }
+}
+
+#[cfg(feature = "debug-spans")]
+impl fmt::Display for SpanId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "span #{}", self.0)
+    }
+}
+
+/// Generational allocation.
+#[derive(Clone, Copy)]
+struct Alloc {
+    slot: &'static Slot,
+    gen: u32,
+    /// Number of allocations made before this one, see [`Ptr::id`].
+    id: u64,
+    /// [`Span`] owning the allocation when this copy was made, [`None`] for global allocations.
+    #[cfg(feature = "debug-spans")]
+    span: Option<SpanId>,
+}
+
+impl PartialEq for Alloc {
+    fn eq(&self, other: &Self) -> bool {
+        core::ptr::eq(self.slot, other.slot) && self.gen == other.gen
+    }
+}
+
+impl Eq for Alloc {}
+
+impl Hash for Alloc {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        core::ptr::hash(self.slot, state);
+        self.gen.hash(state);
+    }
+}
+
+impl Alloc {
+    /// Returns the id of a new allocation, counting the allocations of the current thread.
+    fn next_id() -> u64 {
+        #[cfg(feature = "std")]
+        {
+            NEXT_ALLOC_ID.with(|next| {
+                let id = next.get();
+                next.set(id.checked_add(1).expect("allocation ids ran out"));
+                id
+            })
+        }
+        // Without thread-locals, the allocations of all threads are counted together.
+        #[cfg(not(feature = "std"))]
+        {
+            static NEXT_ALLOC_ID: core::sync::atomic::AtomicU64 =
+                core::sync::atomic::AtomicU64::new(1);
+            NEXT_ALLOC_ID.fetch_add(1, core::sync::atomic::Ordering::Relaxed)
+        }
+    }
+
+    /// Counts a new entry for this allocation in the list of a span, see [`Slot::listed`].
+    fn list(self) -> Self {
+        self.slot.listed.set(self.slot.listed.get() + 1);
+        self
+    }
+
+    /// Counts an entry for this allocation removed from the list of a span.
+    fn unlist(self) -> Self {
+        self.slot.listed.set(self.slot.listed.get() - 1);
+        self
+    }
+
+    /// Identifies this allocation without its interior mutability.
+    #[cfg(any(feature = "serde", feature = "debug-tracking"))]
+    fn key(&self) -> (*const Slot, u32) {
+        (self.slot, self.gen)
+    }
+
+    fn borrow_state(&self) -> BorrowState {
+        if self.check().is_err() {
+            return BorrowState::Dangling;
+        }
+        let value = &self.slot.value;
+        if value.try_borrow_mut().is_ok() {
+            BorrowState::Unborrowed
+        } else if value.try_borrow().is_ok() {
+            BorrowState::Shared
+        } else {
+            BorrowState::Exclusive
+        }
+    }
+
+    fn check(&self) -> Result<(), DanglingError> {
+        let actual = self.slot.gen.get();
+        if self.gen == actual {
+            Ok(())
+        } else {
+            Err(DanglingError {
+                #[cfg(feature = "debug-spans")]
+                span: self.span,
+                #[cfg(feature = "debug-spans")]
+                recycled_by: if cfg!(feature = "shared-pool") {
+                    None
+                } else {
+                    self.slot.recycled_by.get()
+                },
+                ..DanglingError::new(self.gen, actual)
+            })
+        }
+    }
+
+    /// Takes the value out, bumps the slot's generation and returns the slot to the recycled
+    /// pool.
+    ///
+    /// Does nothing if the allocation was already recycled, and only marks it for recycling
+    /// while it's pinned by a [`KeepAlive`].
+    /// If the value is still borrowed, the generation is bumped but the slot only returns to the
+    /// pool once the borrow is released, see [`PoolStorage::reclaim`].
+    /// Slots that run out of generations are retired instead of being returned to the pool, so
+    /// that generations never wrap around and resurrect stale pointers.
+    fn recycle(self) -> Value {
+        self.recycle_impl(false)
+    }
+
+    /// Returns `false` if the allocation was already recycled, or marks it for recycling if it's
+    /// pinned by a [`KeepAlive`].
+    fn begin_recycle(&self) -> bool {
+        if self.check().is_err() {
+            return false;
+        }
+        if self.slot.keep_alive.get() > 0 {
+            self.slot.recycle_pending.set(true);
+            return false;
+        }
+        true
+    }
+
+    #[cfg(feature = "std")]
+    fn raw_handle(&self) -> RawHandle {
+        RawHandle {
+            slot: core::ptr::from_ref(self.slot) as usize,
+            gen: self.gen,
+            id: self.id,
+        }
+    }
+
+    /// Drops the value and recycles the allocation like [`Alloc::recycle`], but keeps the
+    /// value's heap allocation around for the next value of the same type, see [`Slot::boxed`].
+    fn release(self) {
+        drop(self.recycle_impl(true));
+    }
+
+    fn recycle_impl(self, keep_box: bool) -> Value {
+        if !self.begin_recycle() {
+            return Value::Empty;
+        }
+        // The hook may have recycled the allocation or pinned it by a `KeepAlive` itself.
+        #[cfg(feature = "std")]
+        if run_dealloc_hook(self.raw_handle()) && !self.begin_recycle() {
+            return Value::Empty;
+        }
+        let gen = self.gen + 1;
+        self.slot.gen.set(gen);
+        #[cfg(feature = "debug-spans")]
+        self.slot.recycled_by.set(self.span);
+        let value = self
+            .slot
+            .value
+            .try_borrow_mut()
+            .map(|mut value| core::mem::take(&mut *value));
+        let value = match value {
+            Ok(Value::Boxed(value))
+                if keep_box && gen != RETIRED_GEN && self.slot.allocator().is_none() =>
+            {
+                self.slot.keep_box(value);
+                Ok(Value::Empty)
+            }
+            value => value,
+        };
+        with_pool(self.slot.pool, |pool| {
+            pool.update_stats(|stats| {
+                stats.live -= 1;
+                stats.recycled += 1;
+                stats.retired += usize::from(gen == RETIRED_GEN);
+            });
+            if value.is_err() {
+                pool.borrowed.borrow_mut().push(self.slot);
+            } else if gen != RETIRED_GEN {
+                pool.recycle([self.slot]);
+            } else {
+                self.slot.spare.take();
+            }
+        });
+        value.unwrap_or_default()
+    }
+}
+
+/// Leaked storage shared by every generation of an allocation.
+#[derive(Default)]
+struct Slot {
+    /// The value of the current generation, stored inline if it's small enough, see [`Value`] for
+    /// how it's erased.
+    value: RefCell<Value>,
+    gen: GenCell,
+    /// Number of [`KeepAlive`]s pinning the current generation.
+    keep_alive: Cell<usize>,
+    /// Whether the current generation gets recycled once it's no longer pinned.
+    recycle_pending: Cell<bool>,
+    /// Pool the slot was leaked into, [`None`] for the pool of the current thread.
+    pool: Option<&'static PoolStorage>,
+    /// Whether a panic unwound while the current generation's value was mutably borrowed.
+    poisoned: Cell<bool>,
+    /// Whether the current generation's value must not move, see [`Span::alloc_pinned`].
+    pinned: Cell<bool>,
+    /// [`Span`] that recycled the previous generation.
+    #[cfg(feature = "debug-spans")]
+    recycled_by: Cell<Option<SpanId>>,
+    /// Heap allocation of a previous generation's value, see [`Slot::boxed`].
+    spare: Cell<Option<SpareBox>>,
+    /// Type name of the current generation's value, shown by the alternate [`fmt::Debug`] output
+    /// of [`Span`].
+    type_name: Cell<Option<&'static str>>,
+    /// Number of entries for this slot in the allocation lists of spans, which keep the slot's
+    /// chunk from being freed by [`PoolStorage::drain`].
+    listed: Cell<usize>,
+}
+
+impl Slot {
+    /// Number of slots leaked at once, see [`Slot::leak`].
+    const CHUNK_LEN: usize = 64;
+
+    /// Returns `true` if the slot was recycled while its value was borrowed, and still waits for
+    /// the borrow to be released, see [`PoolStorage::reclaim`].
+    fn is_deferred(&self) -> bool {
+        with_pool(self.pool, |pool| {
+            pool.borrowed
+                .try_borrow()
+                .is_ok_and(|borrowed| borrowed.iter().any(|slot| core::ptr::eq(*slot, self)))
+        })
+    }
+
+    /// Allocator of the slot's pool, see [`PoolStorage::with_allocator`].
+    fn allocator(&self) -> heap::Allocator {
+        self.pool.and_then(|pool| pool.allocator)
+    }
+
+    /// Leaks a chunk of slots into `pool`, returning the first one and putting the rest into the
+    /// recycled pool.
+    fn leak(pool: Option<&'static PoolStorage>) -> &'static Self {
+        #[cfg(feature = "shared-pool")]
+        if pool.is_none() {
+            if let Some(slot) = POOL.with(PoolStorage::take_shared) {
+                return slot;
+            }
+        }
+        // Only leak as many slots as the pool can take, besides the one returned.
+        let len = with_pool(pool, |pool| pool.limit.get().saturating_sub(pool.len()))
+            .saturating_add(1)
+            .min(Self::CHUNK_LEN);
+        let (slot, rest) = Self::leak_chunk(pool, len)
+            .split_first()
+            .expect("chunk is not empty");
+        with_pool(pool, |pool| pool.recycle(rest.iter().rev()));
+        slot
+    }
+
+    /// Leaks chunks of slots into `pool` until its recycled pool holds `additional` more
+    /// slots, or as many as its limit allows.
+    fn reserve(pool: Option<&'static PoolStorage>, additional: usize) {
+        with_pool(pool, |storage| {
+            let len = storage
+                .len()
+                .saturating_add(additional)
+                .min(storage.limit.get());
+            while storage.len() < len {
+                let chunk_len = (storage.limit.get() - storage.len()).min(Self::CHUNK_LEN);
+                storage.recycle(Self::leak_chunk(pool, chunk_len).iter().rev());
+            }
+        });
+    }
+
+    /// Leaks a chunk of `len` fresh slots into `pool`, at most [`Slot::CHUNK_LEN`].
+    ///
+    /// Chunks are only shorter than that if the pool's limit wouldn't fit a full one, see
+    /// [`PoolStorage::set_limit`]. The chunk is only ever freed by [`PoolStorage::drain`].
+    fn leak_chunk(pool: Option<&'static PoolStorage>, len: usize) -> &'static [Self] {
+        debug_assert!(len <= Self::CHUNK_LEN);
+        let layout = Layout::array::<Self>(len).expect("chunk is at most a full one");
+        let allocator = pool.and_then(|pool| pool.allocator);
+        let ptr = heap::alloc(allocator, layout).cast::<Self>();
+        for i in 0..len {
+            let slot = Self {
+                pool,
+                ..Self::default()
+            };
+            // SAFETY: The chunk was allocated for `len` slots.
+            unsafe { ptr.as_ptr().add(i).write(slot) };
+        }
+        let chunk = core::ptr::slice_from_raw_parts_mut(ptr.as_ptr(), len);
+        with_pool(pool, |pool| {
+            pool.chunks.borrow_mut().push(chunk);
+            pool.update_stats(|stats| stats.slots += len);
+        });
+        // SAFETY: The chunk is only freed by `PoolStorage::drain`, whose caller guarantees that
+        // no references to it are used afterwards.
+        unsafe { &*chunk }
+    }
+
+    /// Wraps `v` in a [`Value`], boxing it only if it doesn't fit inline, and reusing the spare
+    /// heap allocation if it was made for a `T`.
+    ///
+    /// Values of pools with a custom allocator never reuse spare allocations.
+    fn boxed<T: 'static>(&self, v: T) -> Value {
+        self.boxed_with(|| v)
+    }
+
+    /// Wraps the value returned by `f` in a [`Value`], see [`Slot::boxed`].
+    ///
+    /// Heap memory is allocated before `f` runs, so that the value can be written straight into
+    /// it, and freed again if `f` panics.
+    fn boxed_with<T: 'static>(&self, f: impl FnOnce() -> T) -> Value {
+        if Value::fits_inline::<T>() {
+            return Value::new(f());
+        }
+        if let Some(allocator) = self.allocator() {
+            return Value::allocated(f, allocator);
+        }
+        let uninit: Box<MaybeUninit<T>> = match self.spare.take() {
+            Some(spare) if spare.type_id == TypeId::of::<T>() => {
+                let ptr = spare.ptr.cast::<MaybeUninit<T>>().as_ptr();
+                core::mem::forget(spare);
+                // SAFETY: The spare allocation was made by a `Box<T>` and its value was dropped,
+                // see `Slot::keep_box`.
+                unsafe { Box::from_raw(ptr) }
+            }
+            _ => Box::new_uninit(),
+        };
+        let boxed: Box<T> = Box::write(uninit, f());
+        Value::Boxed(boxed)
+    }
+
+    /// Wraps `v` in a [`Value`] on the heap, even if it would fit inline.
+    fn allocated<T: 'static>(&self, v: T) -> Value {
+        match self.allocator() {
+            Some(allocator) => Value::allocated(|| v, allocator),
+            None => Value::Boxed(Box::new(v)),
+        }
+    }
+
+    /// Drops `value` but keeps its heap allocation as the spare one of this slot.
+    fn keep_box(&self, value: Box<dyn Any>) {
+        let layout = Layout::for_value(&*value);
+        if layout.size() == 0 {
+            return;
+        }
+        let type_id = Any::type_id(&*value);
+        let ptr = Box::into_raw(value);
+        let spare = SpareBox {
+            // SAFETY: Boxes are never null.
+            ptr: unsafe { NonNull::new_unchecked(ptr.cast()) },
+            layout,
+            type_id,
+        };
+        // SAFETY: The value is dropped exactly once, its memory is freed by `spare` even if this
+        // panics.
+        unsafe { core::ptr::drop_in_place(ptr) };
+        self.spare.set(Some(spare));
+    }
+
+    /// Leaks a single slot that gets retired instead of returning to `pool` once recycled.
+    ///
+    /// Counts as a fresh allocation of `pool`.
+    fn leak_retiring(pool: Option<&'static PoolStorage>) -> &'static Self {
+        with_pool(pool, |pool| {
+            pool.update_stats(|stats| {
+                stats.fresh += 1;
+                stats.live += 1;
+                stats.slots += 1;
+            });
+        });
+        // Recycling bumps the generation to `RETIRED_GEN`, so the slot is retired.
+        let slot = Self {
+            gen: GenCell::new(RETIRED_GEN - 1),
+            pool,
+            ..Self::default()
+        };
+        let ptr = heap::alloc(pool.and_then(|pool| pool.allocator), Layout::new::<Self>());
+        // SAFETY: The memory was allocated for a slot, and is never freed.
+        unsafe {
+            ptr.cast::<Self>().as_ptr().write(slot);
+            &*ptr.cast::<Self>().as_ptr()
+        }
+    }
+}
+
+/// Slot taken from its pool but not stored into yet, returned to the pool if dropped, see
+/// [`Span::alloc_with`].
+struct UnusedSlot(&'static Slot);
+
+impl Drop for UnusedSlot {
+    fn drop(&mut self) {
+        with_pool(self.0.pool, |pool| {
+            pool.update_stats(|stats| stats.live -= 1);
+            pool.recycle([self.0]);
+        });
+    }
+}
+
+/// Heap allocation of a dropped boxed value.
+struct SpareBox {
+    ptr: NonNull<u8>,
+    layout: Layout,
+    /// Type of the dropped value.
+    type_id: TypeId,
+}
+
+impl Drop for SpareBox {
+    fn drop(&mut self) {
+        // SAFETY: The allocation was made by a `Box` with this layout, see `Slot::keep_box`.
+        unsafe { alloc::alloc::dealloc(self.ptr.as_ptr(), self.layout) };
+    }
+}
+
+/// Pool of recycled allocations shared by spans.
+///
+/// Every thread has its own pool backing [`Span::new`], which requires the `std` feature.
+/// A [`PoolStorage`] can be used instead through [`Span::new_in`], e.g. without the `std`
+/// feature:
+/// ```
+/// use genalloc::{PoolStorage, Span};
+///
+/// let pool: &'static PoolStorage = Box::leak(Box::new(PoolStorage::new()));
+/// let mut span = Span::new_in(pool);
+/// let ptr = span.alloc(5);
+/// drop(span);
+/// assert!(!ptr.is_valid());
+/// assert_eq!(pool.len(), 64);
+/// ```
+pub struct PoolStorage {
+    recycled: RefCell<VecDeque<&'static Slot>>,
+    limit: Cell<usize>,
+    reuse_policy: Cell<ReusePolicy>,
+    /// Chunks of slots leaked into this pool, see [`Slot::leak`].
+    chunks: RefCell<Vec<*mut [Slot]>>,
+    /// Recycled slots whose values were still borrowed, see [`PoolStorage::reclaim`].
+    borrowed: RefCell<Vec<&'static Slot>>,
+    /// Slots discarded beyond the limit, which are never reused but may still be freed by
+    /// [`PoolStorage::drain`].
+    discarded: RefCell<Vec<&'static Slot>>,
+    /// Counters of [`PoolStorage::stats`], the current pool length is filled in on demand.
+    stats: Cell<SpanStats>,
+    /// Allocator of the slots and values, see [`PoolStorage::with_allocator`].
+    allocator: heap::Allocator,
+}
+
+impl Default for PoolStorage {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Hands the recycled slots of an exiting thread over to the [`SHARED_POOL`].
+#[cfg(feature = "shared-pool")]
+impl Drop for PoolStorage {
+    fn drop(&mut self) {
+        let recycled = self.recycled.get_mut();
+        let mut shared = SHARED_POOL
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        // Slots of user-provided pools keep recycling into their pool.
+        shared.extend(
+            recycled
+                .drain(..)
+                .filter(|slot| slot.pool.is_none())
+                .map(SharedSlot),
+        );
+    }
+}
+
+impl PoolStorage {
+    pub const fn new() -> Self {
+        Self::new_impl(None)
+    }
+
+    /// Creates a pool whose slots and values are allocated by `allocator` instead of the global
+    /// allocator, e.g. to attribute them to a memory budget.
+    ///
+    /// Values that fit inline are stored in their slot, all other values are allocated by
+    /// `allocator`, including the ones of [`Span::alloc_with`] and [`Span::alloc_iter`]. Values
+    /// that were already boxed, e.g. by [`Span::alloc_boxed`], are still freed by the global
+    /// allocator, and [`Span::into_erased`] moves values into global boxes. The bookkeeping of
+    /// spans and of the pool itself, i.e. their lists of allocations, also uses the global
+    /// allocator. Since every slot belongs to its pool, slots of different allocators never mix.
+    pub const fn with_allocator(allocator: &'static dyn core::alloc::GlobalAlloc) -> Self {
+        Self::new_impl(Some(allocator))
+    }
+
+    const fn new_impl(allocator: heap::Allocator) -> Self {
+        Self {
+            recycled: RefCell::new(VecDeque::new()),
+            limit: Cell::new(usize::MAX),
+            reuse_policy: Cell::new(ReusePolicy::Lifo),
+            chunks: RefCell::new(Vec::new()),
+            borrowed: RefCell::new(Vec::new()),
+            discarded: RefCell::new(Vec::new()),
+            stats: Cell::new(SpanStats {
+                reused: 0,
+                fresh: 0,
+                pool_len: 0,
+                peak_pool_len: 0,
+                live: 0,
+                slots: 0,
+                recycled: 0,
+                retired: 0,
+            }),
+            allocator,
+        }
+    }
+
+    /// Returns a snapshot of the allocation statistics of spans using this pool.
+    pub fn stats(&self) -> SpanStats {
+        SpanStats {
+            pool_len: self.len(),
+            ..self.stats.get()
+        }
+    }
+
+    /// Returns the number of allocations in this pool.
+    pub fn len(&self) -> usize {
+        self.recycled.borrow().len()
+    }
+
+    /// Returns `true` if this pool holds no allocations.
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Fills this pool with at least `additional` fresh allocations, so that spans take them
+    /// from the pool instead of leaking new ones.
+    ///
+    /// Allocations are leaked in chunks, so the pool may grow by more than `additional`. It never
+    /// grows beyond its limit though, see [`PoolStorage::set_limit`].
+    pub fn reserve(&'static self, additional: usize) {
+        Slot::reserve(Some(self), additional);
+    }
+
+    /// Shrinks this pool to at most `keep` allocations.
+    ///
+    /// The discarded allocations are never reused, and the spare heap allocations of their
+    /// values are freed right away. Their own storage stays allocated, as stale pointers may still
+    /// check its generation, until [`PoolStorage::drain`] frees it.
+    pub fn shrink(&self, keep: usize) {
+        let mut recycled = self.recycled.borrow_mut();
+        let excess = recycled.len().saturating_sub(keep);
+        self.discard(recycled.drain(..excess));
+        recycled.shrink_to_fit();
+    }
+
+    /// Limits this pool to `max` allocations.
+    ///
+    /// Allocations recycled while the pool is full are discarded, excess allocations already in
+    /// the pool are discarded right away, see [`PoolStorage::shrink`]. Fresh allocations are
+    /// leaked in chunks no larger than the pool can take, so the limit also bounds the storage
+    /// leaked ahead of use.
+    pub fn set_limit(&self, max: usize) {
+        self.limit.set(max);
+        self.shrink(max);
+    }
+
+    /// Sets the order in which allocations are taken from this pool, see [`ReusePolicy`].
+    pub fn set_reuse_policy(&self, policy: ReusePolicy) {
+        self.reuse_policy.set(policy);
+    }
+
+    /// Frees the storage of allocations in this pool, returning the number of freed allocations.
+    ///
+    /// Allocations are leaked in chunks, so only chunks whose allocations are all in the pool or
+    /// were discarded from it, see [`PoolStorage::shrink`], are freed. Chunks are also kept while
+    /// a [`Span`] still lists one of their allocations, e.g. after it was recycled early by
+    /// [`Ptr::take`] or an [`Owned`]. The remaining allocations stay in the pool.
+    ///
+    /// # Safety
+    /// No [`Ptr`], [`Owned`] or other pointer to a freed allocation may be used after this call,
+    /// not even to check its validity, as that reads the freed storage. Since a stale pointer may
+    /// point to any allocation in the pool, every pointer to an allocation recycled into this
+    /// pool must be gone.
+    pub unsafe fn drain(&self) -> usize {
+        let mut recycled = self.recycled.take();
+        let mut discarded = self.discarded.take();
+        let pooled: BTreeSet<*const Slot> = recycled
+            .iter()
+            .chain(&discarded)
+            .map(|slot| core::ptr::from_ref(*slot))
+            .collect();
+        let mut freed = Vec::new();
+        self.chunks.borrow_mut().retain(|&chunk| {
+            // SAFETY: Chunks are only freed below, after being removed from `chunks`.
+            let slots = unsafe { &*chunk };
+            let unused = slots
+                .iter()
+                .all(|slot| pooled.contains(&core::ptr::from_ref(slot)) && slot.listed.get() == 0);
+            if unused {
+                freed.push(chunk);
+            }
+            !unused
+        });
+        let freed_slots: BTreeSet<*const Slot> = freed
+            .iter()
+            // SAFETY: The chunks are not freed yet.
+            .flat_map(|&chunk| unsafe { &*chunk }.iter().map(core::ptr::from_ref))
+            .collect();
+        recycled.retain(|slot| !freed_slots.contains(&core::ptr::from_ref(*slot)));
+        discarded.retain(|slot| !freed_slots.contains(&core::ptr::from_ref(*slot)));
+        self.recycled.replace(recycled);
+        self.discarded.replace(discarded);
+        self.update_stats(|stats| stats.slots -= freed_slots.len());
+        for chunk in freed {
+            let layout = Layout::array::<Slot>(chunk.len()).expect("chunk was allocated");
+            // SAFETY: The chunk was allocated by `Slot::leak_chunk` with this layout and the
+            // allocator of this pool, and the pool no longer references it. The caller guarantees
+            // that no pointer to it is used anymore.
+            unsafe {
+                core::ptr::drop_in_place(chunk);
+                heap::dealloc(self.allocator, NonNull::new_unchecked(chunk.cast()), layout);
+            }
+        }
+        freed_slots.len()
+    }
+
+    /// Returns `slots` to this pool, discarding the ones that don't fit.
+    fn recycle(&self, slots: impl IntoIterator<Item = &'static Slot>) {
+        let mut recycled = self.recycled.borrow_mut();
+        for slot in slots {
+            if recycled.len() < self.limit.get() {
+                recycled.push_back(slot);
+            } else {
+                self.discard([slot]);
+            }
+        }
+        let len = recycled.len();
+        self.update_stats(|stats| stats.peak_pool_len = stats.peak_pool_len.max(len));
+    }
+
+    /// Keeps `slots` out of this pool for good, see [`PoolStorage::shrink`].
+    fn discard(&self, slots: impl IntoIterator<Item = &'static Slot>) {
+        let mut discarded = self.discarded.borrow_mut();
+        for slot in slots {
+            slot.spare.take();
+            discarded.push(slot);
+        }
+    }
+
+    /// Takes the next slot out of this pool, see [`ReusePolicy`].
+    fn pop_recycled(&self) -> Option<&'static Slot> {
+        let mut recycled = self.recycled.borrow_mut();
+        match self.reuse_policy.get() {
+            ReusePolicy::Lifo => recycled.pop_back(),
+            ReusePolicy::Fifo => recycled.pop_front(),
+        }
+    }
+
+    /// Moves up to `n` slots out of this pool into `slots`, in the order of
+    /// [`PoolStorage::pop_recycled`].
+    fn take_recycled(&self, n: usize, slots: &mut Vec<&'static Slot>) {
+        let mut recycled = self.recycled.borrow_mut();
+        let n = n.min(recycled.len());
+        match self.reuse_policy.get() {
+            ReusePolicy::Lifo => {
+                let start = recycled.len() - n;
+                slots.extend(recycled.drain(start..).rev());
+            }
+            ReusePolicy::Fifo => slots.extend(recycled.drain(..n)),
+        }
+    }
+
+    /// Moves up to a chunk of slots from the [`SHARED_POOL`] into this pool, returning one of
+    /// them.
+    #[cfg(feature = "shared-pool")]
+    fn take_shared(&self) -> Option<&'static Slot> {
+        let mut shared = SHARED_POOL
+            .lock()
+            .unwrap_or_else(std::sync::PoisonError::into_inner);
+        let start = shared.len().saturating_sub(Slot::CHUNK_LEN);
+        let mut slots = shared.drain(start..).map(|slot| slot.0);
+        let slot = slots.next()?;
+        self.recycle(slots);
+        Some(slot)
+    }
+
+    /// Drops the values of recycled slots that are no longer borrowed and returns the slots to
+    /// this pool.
+    ///
+    /// Runs whenever a [`Span`] is cleared and before leaking fresh slots.
+    fn reclaim(&self) {
+        let released: Vec<_> = {
+            let mut borrowed = self.borrowed.borrow_mut();
+            if borrowed.is_empty() {
+                return;
+            }
+            let (released, still_borrowed) = borrowed
+                .drain(..)
+                .partition(|slot| slot.value.try_borrow_mut().is_ok());
+            *borrowed = still_borrowed;
+            released
+        };
+        let values: Vec<_> = released.iter().map(|slot| slot.value.take()).collect();
+        self.recycle(
+            released
+                .into_iter()
+                .filter(|slot| slot.gen.get() != RETIRED_GEN),
+        );
+        drop(values);
+    }
+
+    /// Counts `slot` being taken out of this pool by an allocation.
+    fn record_alloc(&self, slot: &Slot) {
+        self.update_stats(|stats| {
+            if slot.gen.get() == 0 {
+                stats.fresh += 1;
+            } else {
+                stats.reused += 1;
+            }
+            stats.live += 1;
+        });
+    }
+
+    fn update_stats(&self, f: impl FnOnce(&mut SpanStats)) {
+        let mut stats = self.stats.get();
+        f(&mut stats);
+        self.stats.set(stats);
+    }
+}
+
+/// Order in which a pool hands out recycled allocations, see [`PoolStorage::set_reuse_policy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ReusePolicy {
+    /// Reuses the allocation recycled last, whose storage is most likely still cached.
+    #[default]
+    Lifo,
+    /// Reuses the allocation recycled first.
+    ///
+    /// A freed allocation then waits for all others in the pool to be reused before it's reused
+    /// itself, so a stale pointer is more likely to fail with [`AccessError::Stale`] than to
+    /// find a value of the same type, e.g. to debug use-after-free bugs. Accesses are slower,
+    /// as reused storage is less likely to be cached.
+    Fifo,
+}
+
+/// Allocation statistics of a pool, see [`stats`] and [`PoolStorage::stats`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpanStats {
+    /// Number of allocations that reused a previously recycled slot.
+    pub reused: usize,
+    /// Number of allocations that used a slot for the first time.
+    pub fresh: usize,
+    /// Number of allocations currently in the pool.
+    pub pool_len: usize,
+    /// Highest number of allocations the pool ever held.
+    pub peak_pool_len: usize,
+    /// Number of allocations that weren't recycled yet.
+    pub live: usize,
+    /// Number of slots created for the pool and not freed by [`PoolStorage::drain`] yet,
+    /// including the ones still waiting for their first allocation.
+    pub slots: usize,
+    /// Number of generations bumped by recycling allocations.
+    pub recycled: usize,
+    /// Number of slots retired for good, whose storage is never reused nor freed, e.g. the ones
+    /// of [`Span::without_recycling`].
+    pub retired: usize,
+}
+
+/// Runs `f` with `pool`, or with the current thread's pool if it's [`None`].
+fn with_pool<R>(pool: Option<&PoolStorage>, f: impl FnOnce(&PoolStorage) -> R) -> R {
+    match pool {
+        Some(pool) => f(pool),
+        #[cfg(feature = "std")]
+        None => POOL.with(f),
+        #[cfg(not(feature = "std"))]
+        None => unreachable!("spans are always created with a pool without the `std` feature"),
+    }
+}
+
+/// Runs `f` with a fresh [`Span`] and recycles its allocations once `f` returns or panics.
+///
+/// Shorthand for [`Span::scope`], requires the `std` feature.
+///
+/// **Returning a [`Ptr`] from `f` always yields a stale pointer**, as its [`Span`] is gone by
+/// the time `f` returns. Return owned values instead:
+/// ```
+/// let sum = genalloc::with_span(|span| {
+///     let ptrs: Vec<_> = (1..=3).map(|i| span.alloc(i)).collect();
+///     ptrs.iter().map(|ptr| *ptr.read()).sum::<i32>()
+/// });
+/// assert_eq!(sum, 6);
+///
+/// let ptr = genalloc::with_span(|span| span.alloc(5));
+/// assert!(!ptr.is_valid());
+/// ```
+#[cfg(feature = "std")]
+pub fn with_span<R>(f: impl FnOnce(&mut Span) -> R) -> R {
+    Span::scope(f)
+}
+
+/// Stores `v` in a generational allocation that lives for the rest of the program.
+///
+/// Unlike allocations of a [`Span`] it's never recycled, so the returned pointer stays valid
+/// unless the value is moved out with [`Ptr::take`]. Even then, the allocation never enters the
+/// recycled pool.
+///
+/// Requires the `std` feature.
+#[cfg(feature = "std")]
+#[must_use]
+pub fn global_alloc<T: 'static>(v: T) -> Ptr<T> {
+    let slot = Slot::leak_retiring(None);
+    *slot.value.borrow_mut() = slot.boxed(v);
+    Ptr {
+        alloc: Alloc {
+            slot,
+            gen: slot.gen.get(),
+            id: Alloc::next_id(),
+            #[cfg(feature = "debug-spans")]
+            span: None,
+        },
+        _marker: PhantomData,
+    }
+}
+
+/// Returns the number of allocations in the current thread's recycled pool.
+///
+/// Requires the `std` feature, see [`PoolStorage::len`].
+#[cfg(feature = "std")]
+pub fn pool_len() -> usize {
+    POOL.with(PoolStorage::len)
+}
+
+/// Fills the current thread's recycled pool with at least `additional` fresh allocations.
+///
+/// Requires the `std` feature, see [`PoolStorage::reserve`].
+#[cfg(feature = "std")]
+pub fn reserve_pool(additional: usize) {
+    Slot::reserve(None, additional);
+}
+
+/// Shrinks the current thread's recycled pool to at most `keep` allocations.
+///
+/// Requires the `std` feature, see [`PoolStorage::shrink`].
+#[cfg(feature = "std")]
+pub fn shrink_pool(keep: usize) {
+    POOL.with(|pool| pool.shrink(keep));
+}
+
+/// Frees the storage of allocations in the current thread's recycled pool, returning the number
+/// of freed allocations.
+///
+/// Requires the `std` feature, see [`PoolStorage::drain`].
+///
+/// # Safety
+/// No pointer to a freed allocation may be used after this call, not even to check its
+/// validity, as that reads the freed storage. Since a stale pointer may point to any allocation
+/// in the pool, every pointer to an allocation recycled by the current thread must be gone.
+#[cfg(feature = "std")]
+pub unsafe fn drain_pool() -> usize {
+    // SAFETY: Guaranteed by the caller.
+    POOL.with(|pool| unsafe { pool.drain() })
+}
+
+/// Returns the allocation statistics of the current thread's pool.
+///
+/// Requires the `std` feature, see [`PoolStorage::stats`].
+#[cfg(feature = "std")]
+pub fn stats() -> SpanStats {
+    POOL.with(PoolStorage::stats)
+}
+
+/// Limits the current thread's recycled pool to `max` allocations.
+///
+/// Requires the `std` feature, see [`PoolStorage::set_limit`].
+#[cfg(feature = "std")]
+#[doc(alias = "set_recycle_capacity")]
+pub fn set_pool_limit(max: usize) {
+    POOL.with(|pool| pool.set_limit(max));
+}
+
+/// Sets the hook called for every allocation of a [`Ptr`] recycled by the current thread,
+/// replacing the previous one.
+///
+/// The hook gets the [`RawHandle`] of the allocation before its generation is bumped, so it
+/// still matches [`Ptr::into_raw`] of the recycled pointer, e.g. to evict it from an index:
+/// ```
+/// use std::{cell::RefCell, collections::HashSet, rc::Rc};
+///
+/// let index = Rc::new(RefCell::new(HashSet::new()));
+/// let hook_index = Rc::clone(&index);
+/// genalloc::set_dealloc_hook(move |handle| {
+///     hook_index.borrow_mut().remove(&handle);
+/// });
+///
+/// let mut span = genalloc::Span::new();
+/// let ptr = span.alloc(5);
+/// index.borrow_mut().insert(ptr.into_raw());
+/// drop(span);
+/// assert!(index.borrow().is_empty());
+/// ```
+///
+/// Allocations pinned by a [`KeepAlive`] are reported once they're actually recycled. A
+/// panicking hook doesn't unwind into the code recycling the allocation, the panic is caught
+/// and the allocation is recycled regardless.
+///
+/// Requires the `std` feature.
+#[cfg(feature = "std")]
+pub fn set_dealloc_hook(f: impl Fn(RawHandle) + 'static) {
+    DEALLOC_HOOK.with(|hook| *hook.borrow_mut() = Some(Rc::new(f)));
+}
+
+/// Hook set by [`set_dealloc_hook`].
+#[cfg(feature = "std")]
+type DeallocHook = Rc<dyn Fn(RawHandle)>;
+
+/// Calls the hook set by [`set_dealloc_hook`], returning `false` if there is none.
+#[cfg(feature = "std")]
+fn run_dealloc_hook(handle: RawHandle) -> bool {
+    // The hook is cloned out, as it may recycle allocations or replace itself.
+    let Some(hook) = DEALLOC_HOOK
+        .try_with(|hook| hook.borrow().clone())
+        .ok()
+        .flatten()
+    else {
+        return false;
+    };
+    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| hook(handle)));
+    true
+}
+
+/// Sets the order in which allocations are taken from the current thread's pool.
+///
+/// Requires the `std` feature, see [`PoolStorage::set_reuse_policy`].
+#[cfg(feature = "std")]
+pub fn set_reuse_policy(policy: ReusePolicy) {
+    POOL.with(|pool| pool.set_reuse_policy(policy));
+}
+
+/// Generation counter of a [`Slot`].
+#[cfg(not(feature = "shared-pool"))]
+type GenCell = Cell<u32>;
+
+/// Generation counter of a [`Slot`], atomic as stale pointers on other threads may still check
+/// it once the slot moved to the [`SHARED_POOL`].
+#[cfg(feature = "shared-pool")]
+#[derive(Default)]
+struct GenCell(core::sync::atomic::AtomicU32);
+
+#[cfg(feature = "shared-pool")]
+impl GenCell {
+    const fn new(gen: u32) -> Self {
+        Self(core::sync::atomic::AtomicU32::new(gen))
+    }
+
+    fn get(&self) -> u32 {
+        self.0.load(core::sync::atomic::Ordering::Acquire)
+    }
+
+    fn set(&self, gen: u32) {
+        self.0.store(gen, core::sync::atomic::Ordering::Release);
+    }
+}
+
+/// Recycled slots of exited threads, taken by threads whose pool runs empty.
+#[cfg(feature = "shared-pool")]
+static SHARED_POOL: std::sync::Mutex<Vec<SharedSlot>> = std::sync::Mutex::new(Vec::new());
+
+/// Slot in the [`SHARED_POOL`].
+#[cfg(feature = "shared-pool")]
+struct SharedSlot(&'static Slot);
+
+// SAFETY: Slots are only moved to the shared pool once the thread owning them exited, and hold
+// no value then. Stale pointers left on that thread only ever read the atomic generation.
+#[cfg(feature = "shared-pool")]
+unsafe impl Send for SharedSlot {}
+
+/// Generation of slots that can't be reused anymore.
+///
+/// No pointer is ever created with this generation, except for [`Ptr::dangling`].
+const RETIRED_GEN: u32 = u32::MAX;
+
+/// Slot of [`Ptr::dangling`], which never holds a value.
+static DANGLING_SLOT: DanglingSlot = DanglingSlot(Slot {
+    value: RefCell::new(Value::Empty),
+    gen: GenCell::new(0),
+    keep_alive: Cell::new(0),
+    recycle_pending: Cell::new(false),
+    pool: None,
+    poisoned: Cell::new(false),
+    pinned: Cell::new(false),
+    #[cfg(feature = "debug-spans")]
+    recycled_by: Cell::new(None),
+    spare: Cell::new(None),
+    type_name: Cell::new(None),
+    listed: Cell::new(0),
+});
+
+/// [`Slot`] shared by all threads, see [`DANGLING_SLOT`].
+struct DanglingSlot(Slot);
+
+// SAFETY: The generation of `Ptr::dangling` never matches the slot's, and every access to a slot
+// checks its generation first, so the slot is only ever read.
+unsafe impl Sync for DanglingSlot {}
+
+#[cfg(feature = "std")]
+std::thread_local! {
+    static POOL: PoolStorage = const { PoolStorage::new() };
+    static DEALLOC_HOOK: RefCell<Option<DeallocHook>> = const { RefCell::new(None) };
+    /// Id of the next allocation, `0` is taken by [`Ptr::dangling`].
+    static NEXT_ALLOC_ID: Cell<u64> = const { Cell::new(1) };
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn ptr_is_copy() {
+    let mut span = Span::new();
+    let ptr_1 = span.alloc("test".to_string());
+    let ptr_2 = ptr_1;
+    assert_eq!(*ptr_1.read(), *ptr_2.read());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn try_read_stale() {
+    let ptr = {
+        let mut span = Span::new();
+        span.alloc(5)
+    };
+    assert!(matches!(
+        ptr.try_read(),
+        Err(AccessError::Stale(DanglingError {
+            expected,
+            actual,
+            ..
+        })) if expected == ptr.generation() && actual == expected + 1
+    ));
+    assert_eq!(ptr.try_write().err(), ptr.try_read().err());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn dangling_ptr() {
+    #[derive(Default)]
+    struct Node {
+        next: Ptr<Node>,
+    }
+
+    let node = Node::default();
+    assert!(!node.next.is_valid());
+    assert!(matches!(
+        node.next.try_read(),
+        Err(AccessError::Stale(err)) if err.is_dangling()
+    ));
+    let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| drop(node.next.read())))
+        .unwrap_err();
+    assert!(err
+        .downcast_ref::<String>()
+        .unwrap()
+        .ends_with(": dangling pointer, see `Ptr::dangling`"));
+
+    let mut span = Span::new();
+    assert!(matches!(
+        span.free(node.next),
+        Err(FreeError::Access(AccessError::Stale(_)))
+    ));
+    assert!(!span.dealloc(node.next));
+    let ptr = span.alloc(Node::default());
+    assert!(ptr != Ptr::dangling() && span.index_of(Ptr::<Node>::dangling()).is_none());
+}
+
+#[cfg(all(feature = "debug-spans", not(feature = "shared-pool")))]
+#[test]
+fn dangling_span_ids() {
+    std::thread::spawn(|| {
+        let mut span = Span::new();
+        let id = span.id();
+        let ptr = span.alloc(5);
+        drop(span);
+        let Err(AccessError::Stale(err)) = ptr.try_read() else {
+            panic!("pointer should be stale");
+        };
+        assert_eq!((err.span(), err.recycled_by()), (Some(id), Some(id)));
+        assert_eq!(
+            err.to_string(),
+            format!(
+                "pointer from span #{0} is stale, expected generation 0 but the allocation is at \
+                 generation 1, last recycled by span #{0}",
+                id.get()
+            )
+        );
+
+        let mut other = Span::new();
+        let mut dst = Span::new();
+        assert!(other.id() > id);
+        let reused = other.alloc(6);
+        assert!(core::ptr::eq(reused.alloc.slot, ptr.alloc.slot));
+        other.transfer(reused, &mut dst).unwrap();
+        let dst_id = dst.id();
+        drop(dst);
+        let Err(AccessError::Stale(err)) = ptr.try_read() else {
+            panic!("pointer should be stale");
+        };
+        assert_eq!((err.span(), err.recycled_by()), (Some(id), Some(dst_id)));
+        let Err(AccessError::Stale(err)) = reused.try_read() else {
+            panic!("pointer should be stale");
+        };
+        assert_eq!(
+            (err.span(), err.recycled_by()),
+            (Some(other.id()), Some(dst_id))
+        );
+    })
+    .join()
+    .unwrap();
+}
+
+#[cfg(all(feature = "std", not(feature = "shared-pool")))]
+#[test]
+#[should_panic(expected = "(the allocation was freed, it's empty)")]
+fn read_stale_empty_panic() {
+    let mut span = Span::new();
+    let ptr = span.alloc(5u8);
+    drop(span);
+    let _ = *ptr.read();
+}
+
+#[cfg(all(feature = "std", not(feature = "shared-pool")))]
+#[test]
+#[should_panic(expected = "(the allocation was freed and reused for another value)")]
+fn write_stale_reused_panic() {
+    let mut span = Span::new();
+    let ptr = span.alloc(5u8);
+    drop(span);
+    let mut span = Span::new();
+    let _ = span.alloc("reused");
+    let err =
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| *ptr.write() = 6)).unwrap_err();
+    let message = err.downcast_ref::<String>().unwrap();
+    assert!(message.starts_with("Ptr<u8>"));
+    assert!(message.contains("expected generation 0 but the allocation is at generation 1"));
+    panic!("{message}");
+}
+
+#[cfg(all(feature = "std", not(feature = "shared-pool")))]
+#[test]
+#[should_panic(
+    expected = "(the allocation was freed while the value was borrowed, the value is dropped \
+                once the borrow is released)"
+)]
+fn read_stale_borrowed_panic() {
+    let mut span = Span::new();
+    let ptr = span.alloc(5u8);
+    let _guard = ptr.read();
+    drop(span);
+    let _ = *ptr.read();
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn try_read_stale_after_recycled_into_other_type() {
+    let ptr = {
+        let mut span = Span::new();
+        span.alloc(5)
+    };
+    let mut span = Span::new();
+    let _other = span.alloc("test".to_string());
+    assert!(matches!(ptr.try_read(), Err(AccessError::Stale(_))));
+    assert!(matches!(ptr.try_write(), Err(AccessError::Stale(_))));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn try_write_already_borrowed() {
+    let mut span = Span::new();
+    let ptr = span.alloc(5);
+    let guard = ptr.read();
+    assert_eq!(ptr.try_write().err(), Some(AccessError::AlreadyBorrowed));
+    assert_eq!(*ptr.try_read().unwrap(), *guard);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn is_valid() {
+    let mut span = Span::new();
+    let ptr = span.alloc(5);
+    assert!(ptr.is_valid());
+    drop(span);
+    assert!(!ptr.is_valid());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn is_valid_after_recycled_into_other_type() {
+    let old = {
+        let mut span = Span::new();
+        span.alloc(5)
+    };
+    let mut span = Span::new();
+    let new = span.alloc("test".to_string());
+    assert!(!old.is_valid());
+    assert!(new.is_valid());
+    assert_eq!(new.generation(), old.generation() + 1);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn is_valid_while_write_guard_is_held() {
+    let mut span = Span::new();
+    let ptr = span.alloc(5);
+    let _guard = ptr.write();
+    assert!(ptr.is_valid());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn take() {
+    let mut span = Span::new();
+    let ptr_1 = span.alloc("test".to_string());
+    let ptr_2 = ptr_1;
+    assert_eq!(ptr_1.take().unwrap(), "test");
+    assert!(!ptr_2.is_valid());
+    assert!(matches!(ptr_2.take(), Err(AccessError::Stale(_))));
+
+    let ptr_3 = span.alloc(5);
+    assert_eq!(ptr_3.generation(), ptr_1.generation() + 1);
+    drop(span);
+    assert!(matches!(
+        ptr_3.try_read(),
+        Err(AccessError::Stale(DanglingError { expected, actual, .. }))
+            if expected == ptr_3.generation() && actual == expected + 1
+    ));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn clear() {
+    let mut span = Span::new();
+    let old = span.alloc(5);
+    span.clear();
+    assert!(!old.is_valid());
+    let new = span.alloc(6);
+    assert!(new.is_valid());
+    assert_eq!(*new.read(), 6);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn type_histogram() {
+    let mut span = Span::new();
+    let _ = span.alloc_iter(0..3);
+    let _ = span.alloc("text");
+    let freed = span.alloc(5u8);
+    span.free(freed).unwrap();
+    let _ = span.alloc_slice_copy(&[1, 2]);
+    let histogram = span.type_histogram();
+    assert_eq!(histogram.len(), 3);
+    assert_eq!(histogram[&TypeId::of::<i32>()], 3);
+    assert_eq!(histogram[&TypeId::of::<&str>()], 1);
+    assert_eq!(histogram[&TypeId::of::<Box<[i32]>>()], 1);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn checkpoint_rollback() {
+    let mut span = Span::new();
+    let before = span.alloc(1);
+    let freed = span.alloc(2);
+    let checkpoint = span.checkpoint();
+    let after = span.alloc(3);
+    let copy = span.alloc_copy(4);
+    let nested = span.checkpoint();
+    let inner = span.alloc(5);
+    span.free(freed).unwrap();
+    span.rollback(nested);
+    assert!(!inner.is_valid());
+    assert!(after.is_valid() && copy.is_valid());
+    let _ = span.alloc(6);
+    span.rollback(checkpoint);
+    assert!(before.is_valid());
+    assert!(!after.is_valid() && !copy.is_valid());
+    assert_eq!(span.len(), 1);
+
+    let mut other = Span::new();
+    let checkpoint = other.checkpoint();
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        span.rollback(checkpoint);
+    }));
+    assert!(result.is_err());
+    assert!(before.is_valid());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn pinned_future() {
+    use core::{
+        future::Future,
+        task::{Context, Poll},
+    };
+
+    struct YieldOnce(bool);
+
+    impl Future for YieldOnce {
+        type Output = ();
+
+        fn poll(mut self: Pin<&mut Self>, _: &mut Context) -> Poll<()> {
+            if core::mem::replace(&mut self.0, true) {
+                Poll::Ready(())
+            } else {
+                Poll::Pending
+            }
+        }
+    }
+
+    let mut span = Span::new();
+    let ptr = span.alloc_pinned(async {
+        let values = [1, 2, 3];
+        let first = &values[0];
+        YieldOnce(false).await;
+        *first
+    });
+    let mut cx = Context::from_waker(std::task::Waker::noop());
+    assert!(ptr.write_pin().as_mut().poll(&mut cx).is_pending());
+    let plain = span.iter_of().next().unwrap();
+    assert_eq!(plain, ptr.ptr());
+    assert!(matches!(plain.try_write(), Err(AccessError::Pinned)));
+    assert!(matches!(plain.take(), Err(AccessError::Pinned)));
+    assert!(matches!(plain.try_swap(plain), Err(AccessError::Pinned)));
+    assert!(plain.write_pinned().as_mut().poll(&mut cx).is_ready());
+
+    let unpinned = span.alloc(YieldOnce(false));
+    assert!(matches!(
+        unpinned.try_write_pinned(),
+        Err(AccessError::NotPinned)
+    ));
+    assert!(span.into_erased().len() == 1);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn dealloc_hook() {
+    let recycled = Rc::new(RefCell::new(Vec::new()));
+    let hook_recycled = Rc::clone(&recycled);
+    set_dealloc_hook(move |handle| {
+        hook_recycled.borrow_mut().push(handle);
+        panic!("hooks are isolated");
+    });
+    let mut span = Span::new();
+    let freed = span.alloc(1);
+    let cleared = span.alloc(2);
+    span.free(freed).unwrap();
+    span.clear();
+    let kept = span.alloc(3);
+    let keep_alive = kept.keep_alive();
+    drop(span);
+    assert_eq!(*recycled.borrow(), [freed.into_raw(), cleared.into_raw()]);
+    drop(keep_alive);
+    assert_eq!(recycled.borrow().last(), Some(&kept.into_raw()));
+    set_dealloc_hook(|_| {});
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn gen_alloc() {
+    struct CountingSpan {
+        span: Span,
+        allocs: usize,
+    }
+
+    impl GenAlloc for CountingSpan {
+        fn alloc<T: 'static>(&mut self, v: T) -> Ptr<T> {
+            self.allocs += 1;
+            self.span.alloc(v)
+        }
+
+        fn alloc_boxed<T: ?Sized + Pointee>(&mut self, v: Box<T>) -> Ptr<T> {
+            self.allocs += 1;
+            self.span.alloc_boxed(v)
+        }
+    }
+
+    fn spawn(alloc: &mut impl GenAlloc) -> (Ptr<u32>, Ptr<str>) {
+        (alloc.alloc(100), alloc.alloc_boxed("player".into()))
+    }
+
+    let mut counting = CountingSpan {
+        span: Span::new(),
+        allocs: 0,
+    };
+    let (health, name) = spawn(&mut counting);
+    assert_eq!((*health.read(), &*name.read()), (100, "player"));
+    assert_eq!(counting.allocs, 2);
+    assert_eq!(counting.span.len(), 2);
+
+    let mut child = counting.span.child();
+    let (health, _) = spawn(&mut child);
+    drop(counting);
+    assert!(!health.is_valid());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn alloc_cyclic() {
+    struct Node {
+        value: u32,
+        prev: Ptr<Node>,
+        next: Ptr<Node>,
+    }
+
+    let mut span = Span::new();
+    let a = span.alloc_cyclic(|a| {
+        assert!(matches!(
+            a.try_read(),
+            Err(AccessError::TypeMismatch { actual: None, .. })
+        ));
+        Node {
+            value: 1,
+            prev: a,
+            next: a,
+        }
+    });
+    assert_eq!(a.read().next, a);
+    let b = span.alloc_cyclic(|b| {
+        let mut node = a.write();
+        node.prev = b;
+        node.next = b;
+        Node {
+            value: 2,
+            prev: a,
+            next: a,
+        }
+    });
+
+    let forward = [a, a.read().next, a.read().next.read().next];
+    assert!(forward.map(|node| node.read().value).eq(&[1, 2, 1]));
+    let backward = [b, b.read().prev, b.read().prev.read().prev];
+    assert!(backward.map(|node| node.read().value).eq(&[2, 1, 2]));
+}
+
+#[test]
+fn ptr_ids() {
+    let pool: &'static PoolStorage = Box::leak(Box::new(PoolStorage::new()));
+    let mut span = Span::new_in(pool);
+    let ptrs = span.alloc_iter(0..3);
+    #[allow(clippy::mutable_key_type)] // `Ord` only depends on the id, address and generation.
+    let ordered: BTreeSet<_> = ptrs.iter().rev().copied().collect();
+    assert!(ordered.into_iter().eq(ptrs.iter().copied()));
+
+    // The reuse of the oldest slot still sorts after the pointers allocated before it.
+    let stale = ptrs[0];
+    assert!(span.dealloc(stale));
+    let reused = span.alloc(3);
+    assert_eq!(reused.into_raw().slot, stale.into_raw().slot);
+    assert!(reused.id() > ptrs[2].id());
+    #[allow(clippy::mutable_key_type)]
+    let ordered: BTreeSet<_> = [reused, ptrs[2], ptrs[1]].into_iter().collect();
+    assert!(ordered.into_iter().eq([ptrs[1], ptrs[2], reused]));
+    // SAFETY: The allocation was not freed.
+    let rebuilt = unsafe { Ptr::<i32>::from_raw(stale.into_raw()) };
+    assert_eq!(rebuilt.id(), stale.id());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn raw_value_ptr() {
+    let mut span = Span::new();
+    let inline = span.alloc(5u32);
+    let boxed = span.alloc([0u8; 64]);
+    let name = span.alloc_boxed::<str>("name".into());
+
+    let ptr = inline.as_mut_ptr();
+    // SAFETY: The value wasn't borrowed since the pointer was returned.
+    unsafe { *ptr += 1 };
+    *inline.write() += 1;
+    assert_eq!(core::ptr::from_ref(&*inline.read()), ptr.cast_const());
+    let ptr = inline.as_ptr();
+    // SAFETY: As above.
+    assert_eq!(unsafe { *ptr }, 7);
+
+    let ptr = boxed.as_ptr();
+    boxed.write()[0] = 1;
+    assert_eq!(boxed.as_mut_ptr().cast_const(), ptr);
+    // SAFETY: As above.
+    assert_eq!(unsafe { (*boxed.as_ptr())[0] }, 1);
+    // SAFETY: As above.
+    assert_eq!(unsafe { &*name.as_ptr() }, "name");
+
+    let _guard = inline.write();
+    assert!(std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| inline.as_ptr())).is_err());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn rollback_without_allocs() {
+    let mut span = Span::new();
+    let before = span.alloc(1);
+    let empty = span.checkpoint();
+    span.rollback(empty);
+    assert!(before.is_valid());
+
+    let outer = span.checkpoint();
+    let _ = span.alloc(2);
+    let inner = span.checkpoint();
+    span.rollback(outer);
+    let after = span.alloc(3);
+    // `inner` now rolls back to the position of `outer`.
+    span.rollback(inner);
+    assert!(before.is_valid() && !after.is_valid());
+    assert_eq!(span.len(), 1);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn into_erased() {
+    let mut span = Span::new();
+    let ptr = span.alloc(5);
+    let _ = span.alloc_str("text");
+    let pinned = span.alloc(6).keep_alive();
+    let values = span.into_erased();
+    assert_eq!(values.len(), 2);
+    assert_eq!(values[0].downcast_ref::<i32>(), Some(&5));
+    assert_eq!(&**values[1].downcast_ref::<Box<str>>().unwrap(), "text");
+    assert!(!ptr.is_valid());
+    assert_eq!(*pinned.read(), 6);
+}
+
+#[cfg(all(test, feature = "std"))]
+struct DropCounter(std::rc::Rc<Cell<usize>>);
+
+#[cfg(all(test, feature = "std"))]
+impl Drop for DropCounter {
+    fn drop(&mut self) {
+        self.0.set(self.0.get() + 1);
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn set_and_replace() {
+    let drops = std::rc::Rc::new(Cell::new(0));
+    let mut span = Span::new();
+    let ptr = span.alloc(DropCounter(drops.clone()));
+    let boxed = core::ptr::from_ref(&*ptr.read());
+
+    ptr.set(DropCounter(drops.clone()));
+    assert_eq!(drops.get(), 1);
+    assert_eq!(core::ptr::from_ref(&*ptr.read()), boxed);
+
+    let old = ptr.replace(DropCounter(drops.clone()));
+    assert_eq!(drops.get(), 1);
+    drop(old);
+    assert_eq!(drops.get(), 2);
+
+    drop(span);
+    assert_eq!(drops.get(), 3);
+    assert!(matches!(
+        ptr.try_set(DropCounter(drops.clone())),
+        Err(AccessError::Stale(_))
+    ));
+    assert_eq!(drops.get(), 4);
+}
+
+#[cfg(all(feature = "std", not(feature = "shared-pool")))]
+#[test]
+fn zero_sized_allocs() {
+    struct Token;
+
+    std::thread::spawn(|| {
+        let mut span = Span::new();
+        let tokens: Vec<_> = (0..100).map(|_| span.alloc(Token)).collect();
+        let _ = span.alloc(());
+        drop(span);
+        let len = pool_len();
+        assert!(tokens.iter().all(|token| !token.is_valid()));
+        for _ in 0..10 {
+            let mut span = Span::new();
+            let tokens: Vec<_> = (0..100).map(|_| span.alloc(Token)).collect();
+            let _ = span.alloc(());
+            let _ = &*tokens[0].read();
+            let _ = &mut *tokens[1].write();
+            span.free(tokens[2]).unwrap();
+            assert!(!tokens[2].is_valid() && tokens[3].is_valid());
+        }
+        assert_eq!(pool_len(), len);
+        assert!(POOL.with(|pool| pool
+            .recycled
+            .borrow()
+            .iter()
+            .all(|slot| slot.spare.take().is_none())));
+    })
+    .join()
+    .unwrap();
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn reuse_boxes() {
+    std::thread::spawn(|| {
+        let drops = std::rc::Rc::new(Cell::new(0));
+        let mut span = Span::new();
+        let ptr = span.alloc((DropCounter(drops.clone()), [0u64; 4]));
+        let addr = core::ptr::from_ref(&*ptr.read()).addr();
+        span.clear();
+        assert_eq!(drops.get(), 1);
+
+        let ptr = span.alloc((DropCounter(drops.clone()), [1u64; 4]));
+        assert_eq!(core::ptr::from_ref(&*ptr.read()).addr(), addr);
+        assert_eq!(ptr.read().1, [1; 4]);
+        span.clear();
+        let other = span.alloc(5u32);
+        assert!(core::ptr::eq(other.alloc.slot, ptr.alloc.slot));
+        // Inline values leave the spare allocation to the next boxed value.
+        assert!(other.alloc.slot.spare.take().is_some());
+        drop(span);
+        assert_eq!(drops.get(), 2);
+    })
+    .join()
+    .unwrap();
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn inline_values() {
+    fn is_inline<T: ?Sized>(ptr: Ptr<T>) -> bool {
+        matches!(*ptr.alloc.slot.value.borrow(), Value::Inline(_))
+    }
+
+    let drops = std::rc::Rc::new(Cell::new(0));
+    let mut span = Span::new();
+    let at_threshold = span.alloc([1u64; 3]);
+    let over_threshold = span.alloc([1u8; 25]);
+    let over_aligned = span.alloc(1u128);
+    assert!(is_inline(at_threshold));
+    assert!(!is_inline(over_threshold) && !is_inline(over_aligned));
+    assert_eq!(*at_threshold.read(), [1; 3]);
+    assert_eq!(*over_threshold.read(), [1; 25]);
+
+    let inline = span.alloc((DropCounter(drops.clone()), 5));
+    let boxed = span.alloc((DropCounter(drops.clone()), [0u64; 3]));
+    assert!(is_inline(inline) && !is_inline(boxed));
+    inline.write().1 += 1;
+    assert_eq!(span.free(inline).unwrap().1, 6);
+    assert_eq!(drops.get(), 1);
+    let slot = inline.alloc.slot;
+    let reused = span.alloc((DropCounter(drops.clone()), [1u64; 3]));
+    assert!(core::ptr::eq(reused.alloc.slot, slot) && !is_inline(reused));
+    assert_eq!(reused.read().1, [1; 3]);
+    drop(span);
+    assert_eq!(drops.get(), 3);
+
+    let mut span = Span::new();
+    let _ = span.alloc(String::from("text"));
+    let _ = span.alloc(());
+    let values = span.into_erased();
+    assert_eq!(values[0].downcast_ref::<String>().unwrap(), "text");
+    assert!(values[1].is::<()>());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn replace_while_read_guard_is_held() {
+    let mut span = Span::new();
+    let ptr = span.alloc(5);
+    let guard = ptr.read();
+    assert_eq!(ptr.try_replace(6), Err(AccessError::AlreadyBorrowed));
+    assert_eq!(*guard, 5);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn shrink_span() {
+    let mut span = Span::with_capacity(100);
+    let ptrs: Vec<_> = (0..50).map(|i| span.alloc(i)).collect();
+    span.free(ptrs[0]).unwrap();
+    span.shrink_to(10);
+    assert!(span.allocs.capacity() >= 49 && span.allocs.capacity() < 100);
+    assert!(ptrs[1..].iter().all(|ptr| ptr.is_valid()));
+    span.clear();
+    span.shrink_to(10);
+    assert_eq!(span.capacity(), 10);
+    span.shrink_to_fit();
+    assert_eq!(span.capacity(), 0);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn span_capacity() {
+    let mut span = Span::with_capacity(10);
+    assert!(span.capacity() >= 10);
+    let buffer = span.allocs.as_ptr();
+    let _ = span.alloc_iter(0..5);
+    let _: Vec<_> = (5..10).map(|i| span.alloc(i)).collect();
+    assert_eq!(span.allocs.as_ptr(), buffer);
+
+    span.reserve(20);
+    assert!(span.capacity() >= 30);
+    let capacity = span.capacity();
+    let buffer = span.allocs.as_ptr();
+    let _: Vec<_> = (0..20).map(|i| span.alloc(i)).collect();
+    assert_eq!((span.allocs.as_ptr(), span.capacity()), (buffer, capacity));
+    assert_eq!(span.len(), 30);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn clear_keeps_capacity() {
+    let mut span = Span::new();
+    let old: Vec<_> = (0..100).map(|i| span.alloc(i)).collect();
+    let capacity = span.capacity();
+    span.clear();
+    assert_eq!(span.capacity(), capacity);
+    let new = span.alloc(100);
+    assert!(core::ptr::eq(new.alloc.slot, old[99].alloc.slot));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn len() {
+    let mut span = Span::new();
+    assert!(span.is_empty());
+    let ptr = span.alloc(5);
+    let _ = span.alloc(6);
+    assert_eq!(span.len(), 2);
+    ptr.take().unwrap();
+    assert_eq!(span.len(), 1);
+    span.clear();
+    assert!(span.is_empty());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn update() {
+    let mut span = Span::new();
+    let ptr = span.alloc(5);
+    let other = ptr.update(|v| {
+        *v += 1;
+        let mut span = Span::new();
+        let other = span.alloc(*v);
+        let v = *other.read();
+        drop(span);
+        v
+    });
+    assert_eq!(*ptr.read(), 6);
+    assert_eq!(other, 6);
+    assert_eq!(ptr.try_update(|v| *v), Ok(6));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn update_panic() {
+    let mut span = Span::new();
+    let ptr = span.alloc(5);
+    let result =
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| ptr.update(|_| panic!())));
+    assert!(result.is_err());
+    assert!(ptr.is_poisoned());
+    ptr.clear_poison();
+    assert_eq!(*ptr.write(), 5);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn checked_access() {
+    let mut span = Span::new();
+    let ptr = span.alloc(5);
+    {
+        let _read = ptr.read();
+        assert_eq!(ptr.checked_read().as_deref(), Some(&5));
+        assert!(ptr.checked_write().is_none());
+    }
+    {
+        let mut write = ptr.checked_write().unwrap();
+        *write += 1;
+        assert!(ptr.checked_read().is_none());
+        assert!(ptr.checked_write().is_none());
+    }
+    assert_eq!(*ptr.checked_read().unwrap(), 6);
+    drop(span);
+    let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        ptr.checked_read().is_none()
+    }))
+    .unwrap_err();
+    assert!(err.downcast_ref::<String>().unwrap().contains("is stale"));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn ptr_raw_parts() {
+    let mut span = Span::new();
+    let ptr = span.alloc(5);
+    let (addr, gen, id) = ptr.into_raw_parts();
+    // SAFETY: The parts come from a pointer of this thread, and the pool is never drained.
+    let rebuilt = unsafe { Ptr::<i32>::from_raw_parts(addr, gen, id) };
+    assert_eq!(rebuilt, ptr);
+    assert_eq!(*rebuilt.read(), 5);
+    drop(span);
+    // SAFETY: As above, the allocation stays leaked once recycled.
+    let stale = unsafe { Ptr::<i32>::from_raw_parts(addr, gen, id) };
+    assert!(matches!(stale.try_read(), Err(AccessError::Stale(_))));
+
+    let mut span = Span::new();
+    let ptr = span.alloc("text");
+    let raw = ptr.into_raw();
+    assert_eq!((raw.slot, raw.gen, raw.id), ptr.into_raw_parts());
+    // SAFETY: The handle comes from a pointer of this thread.
+    assert_eq!(*unsafe { Ptr::<&str>::from_raw(raw) }.read(), "text");
+    // SAFETY: As above, a mismatched type is caught by the access.
+    let mismatched = unsafe { Ptr::<i32>::from_raw(raw) };
+    assert!(matches!(
+        mismatched.try_read(),
+        Err(AccessError::TypeMismatch { .. })
+    ));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn ptr_borrow_state() {
+    let mut span = Span::new();
+    let ptr = span.alloc(5);
+    assert_eq!(ptr.borrow_state(), BorrowState::Unborrowed);
+    {
+        let _read = ptr.read();
+        assert_eq!(ptr.borrow_state(), BorrowState::Shared);
+    }
+    {
+        let _write = ptr.write();
+        assert_eq!(ptr.borrow_state(), BorrowState::Exclusive);
+        assert_eq!(ptr.borrow_state(), BorrowState::Exclusive);
+    }
+    assert_eq!(ptr.borrow_state(), BorrowState::Unborrowed);
+    drop(span);
+    assert_eq!(ptr.borrow_state(), BorrowState::Dangling);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn poison() {
+    let mut span = Span::new();
+    let ptr = span.alloc(vec![1]);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let mut guard = ptr.write();
+        guard.push(2);
+        panic!();
+    }));
+    assert!(result.is_err());
+    assert_eq!(ptr.try_read().err(), Some(AccessError::Poisoned));
+    assert_eq!(ptr.try_write().err(), Some(AccessError::Poisoned));
+    assert_eq!(format!("{:?}", ptr.debug_value()), "<poisoned>");
+    ptr.clear_poison();
+    assert_eq!(*ptr.read(), [1, 2]);
+
+    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        let _ = ptr.read();
+        panic!();
+    }));
+    assert!(!ptr.is_poisoned());
+    span.clear();
+    let ptr = span.alloc(5);
+    assert!(!ptr.is_poisoned());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn free() {
+    let mut span = Span::new();
+    let ptr_1 = span.alloc("test".to_string());
+    let ptr_2 = ptr_1;
+    let _ = span.alloc(5);
+    assert_eq!(span.free(ptr_1).unwrap(), "test");
+    assert!(!ptr_2.is_valid());
+    assert_eq!(span.len(), 1);
+    assert!(matches!(
+        span.free(ptr_2),
+        Err(FreeError::Access(AccessError::Stale(_)))
+    ));
+
+    let mut other = Span::new();
+    let foreign = other.alloc(6);
+    assert_eq!(span.free(foreign), Err(FreeError::Foreign));
+    assert!(foreign.is_valid());
+}
+
+#[cfg(feature = "std")]
+#[test]
+#[allow(clippy::mutable_key_type)] // `Hash` only depends on the slot address and generation.
+fn ptr_identity() {
+    use std::collections::{BTreeSet, HashSet};
+
+    let old = {
+        let mut span = Span::new();
+        span.alloc(5)
+    };
+    let mut span = Span::new();
+    let new = span.alloc(5);
+    let copy = new;
+    assert!(new == copy);
+    assert!(new != old);
+    assert!(Ptr::ptr_eq(&new, &copy));
+    assert!(!Ptr::ptr_eq(&new, &old));
+    let mut other = Span::new();
+    assert!(!Ptr::ptr_eq(&new, &other.alloc(5)));
+
+    let _guard = new.write();
+    let set = HashSet::from([new, old]);
+    assert!(set.contains(&copy));
+    assert_eq!(set.len(), 2);
+    let set = BTreeSet::from([new, old, copy]);
+    assert!(set.contains(&copy));
+    assert_eq!(set.len(), 2);
+}
+
+#[cfg(feature = "debug-tracking")]
 #[test]
-fn ptr_is_copy() {
+fn tracked_origin() {
+    let mut span = Span::named("frame-scratch");
+    let line = line!() + 1;
+    let ptr = span.alloc(5);
+    let bulk = span.alloc_iter([6]);
+    assert_eq!(span.name(), Some("frame-scratch"));
+    drop(span);
+    let err = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| *ptr.read())).unwrap_err();
+    let message = err.downcast_ref::<String>().unwrap();
+    assert!(message.starts_with(&format!("Ptr<i32> allocated at {}:{line}:", file!())));
+    assert!(message.contains(" in span 'frame-scratch': pointer "));
+    let err =
+        std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| *bulk[0].read())).unwrap_err();
+    let message = err.downcast_ref::<String>().unwrap();
+    assert!(message.contains(&format!("{}:{}", file!(), line + 1)));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn debug() {
     let mut span = Span::new();
-    let ptr_1 = span.alloc("test".to_string());
-    let ptr_2 = ptr_1;
-    assert_eq!(*ptr_1.read(), *ptr_2.read());
+    let ptr = span.alloc(5);
+    assert!(format!("{ptr:?}").starts_with("Ptr<i32> {"));
+    assert!(format!("{ptr:?}").contains("live: true"));
+    assert_eq!(format!("{:?}", ptr.debug_value()), "5");
+    {
+        let _guard = ptr.write();
+        assert!(format!("{ptr:?}").contains("live: true"));
+        assert_eq!(format!("{:?}", ptr.debug_value()), "<borrowed>");
+    }
+    drop(span);
+    assert!(format!("{ptr:?}").contains("live: false"));
+    assert_eq!(format!("{:?}", ptr.debug_value()), "<stale>");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn for_each_any() {
+    use std::any::TypeId;
+
+    let mut span = Span::new();
+    let _ = span.alloc(5);
+    let ptr = span.alloc("test".to_string());
+    let taken = span.alloc(6);
+    taken.take().unwrap();
+
+    let mut type_ids = Vec::new();
+    span.for_each_any(|any| type_ids.push(any.type_id()));
+    assert_eq!(type_ids, [TypeId::of::<i32>(), TypeId::of::<String>()]);
+
+    let _guard = ptr.write();
+    let mut count = 0;
+    span.for_each_any(|_| count += 1);
+    assert_eq!(count, 1);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn scope() {
+    let ptr = Span::scope(|span| {
+        let ptr = span.alloc(5);
+        assert_eq!(*ptr.read(), 5);
+        ptr
+    });
+    assert!(!ptr.is_valid());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn scope_panic() {
+    let ptr = Cell::new(None);
+    let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+        Span::scope(|span| {
+            ptr.set(Some(span.alloc(5)));
+            panic!();
+        })
+    }));
+    assert!(result.is_err());
+    assert!(!ptr.get().unwrap().is_valid());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn child_dropped_before_parent() {
+    let mut parent = Span::new();
+    let parent_ptr = parent.alloc(1);
+    let mut child = parent.child();
+    let child_ptr = child.alloc(2);
+    drop(child);
+    assert!(!child_ptr.is_valid());
+    assert!(parent_ptr.is_valid());
+
+    let mut child = parent.child();
+    let reused_ptr = child.alloc(3);
+    assert!(!child_ptr.is_valid());
+    drop(parent);
+    assert!(!reused_ptr.is_valid());
+    assert!(child.is_empty());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn child_outlives_parent() {
+    let mut parent = Span::new();
+    let mut child = parent.child();
+    let child_ptr = child.alloc(2);
+    drop(parent);
+    assert!(!child_ptr.is_valid());
+    let ptr = child.alloc(3);
+    assert_eq!(*ptr.read(), 3);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn nested_children() {
+    let mut parent = Span::new();
+    let mut child = parent.child();
+    let mut grandchild = child.child();
+    let child_ptr = child.alloc(2);
+    let grandchild_ptr = grandchild.alloc(3);
+
+    child.clear();
+    assert!(!child_ptr.is_valid());
+    assert!(!grandchild_ptr.is_valid());
+
+    let grandchild_ptr = grandchild.alloc(3);
+    drop(parent);
+    assert!(!grandchild_ptr.is_valid());
+    assert!(child.is_empty());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn weak_ptr() {
+    let weak = {
+        let mut span = Span::new();
+        let ptr = span.alloc(5);
+        let weak = ptr.downgrade();
+        assert!(weak.upgrade().unwrap() == ptr);
+        weak
+    };
+    assert!(weak.upgrade().is_none());
+    let mut span = Span::new();
+    let _ = span.alloc("test".to_string());
+    assert!(weak.upgrade().is_none());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn generation_overflow() {
+    let ptr = {
+        let mut span = Span::new();
+        span.alloc(5)
+    };
+    ptr.alloc.slot.gen.set(RETIRED_GEN - 1);
+    let mut span = Span::new();
+    let last = span.alloc(6);
+    assert!(std::ptr::eq(last.alloc.slot, ptr.alloc.slot));
+    assert_eq!(last.generation(), RETIRED_GEN - 1);
+    drop(span);
+    assert!(!last.is_valid());
+
+    let mut span = Span::new();
+    let next = span.alloc(7);
+    assert!(!std::ptr::eq(next.alloc.slot, ptr.alloc.slot));
+    assert!(!ptr.is_valid());
+    assert!(!last.is_valid());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn shrink_recycled_pool() {
+    std::thread::spawn(|| {
+        assert_eq!(pool_len(), 0);
+        let mut span = Span::new();
+        for i in 0..100 {
+            let _ = span.alloc(i);
+        }
+        drop(span);
+        assert_eq!(pool_len(), 2 * Slot::CHUNK_LEN);
+        shrink_pool(10);
+        assert_eq!(pool_len(), 10);
+        shrink_pool(20);
+        assert_eq!(pool_len(), 10);
+    })
+    .join()
+    .unwrap();
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn pool_limit() {
+    std::thread::spawn(|| {
+        set_pool_limit(10);
+        let mut span = Span::new();
+        assert_eq!(pool_len(), 0);
+        let ptrs: Vec<_> = (0..20).map(|i| span.alloc(i)).collect();
+        // Fresh slots are leaked 11 at a time, the one allocated and the 10 the pool takes.
+        #[cfg(not(feature = "shared-pool"))]
+        assert_eq!(stats().slots, 22);
+        drop(span);
+        assert_eq!(pool_len(), 10);
+        assert!(ptrs.iter().all(|ptr| !ptr.is_valid()));
+        set_pool_limit(5);
+        assert_eq!(pool_len(), 5);
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+fn pool_limit_bounds_fresh_slots() {
+    let pool: &'static PoolStorage = Box::leak(Box::new(PoolStorage::new()));
+    pool.set_limit(0);
+    let mut span = Span::new_in(pool);
+    let _: Vec<_> = (0..100).map(|i| span.alloc(i)).collect();
+    assert_eq!(pool.stats().slots, 100);
+
+    pool.set_limit(10);
+    let _ = span.alloc_iter(0..20);
+    assert_eq!((pool.stats().slots, pool.len()), (122, 2));
+    pool.reserve(100);
+    assert_eq!((pool.stats().slots, pool.len()), (130, 10));
+}
+
+#[test]
+fn drain_discarded() {
+    let pool: &'static PoolStorage = Box::leak(Box::new(PoolStorage::new()));
+    pool.reserve(2 * Slot::CHUNK_LEN);
+    pool.shrink(1);
+    assert_eq!(pool.len(), 1);
+    assert_eq!(pool.stats().slots, 2 * Slot::CHUNK_LEN);
+
+    // SAFETY: No pointers to the pool's allocations were ever created.
+    assert_eq!(unsafe { pool.drain() }, 2 * Slot::CHUNK_LEN);
+    assert_eq!(pool.stats().slots, 0);
+    assert!(pool.is_empty());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn reserve_recycled_pool() {
+    std::thread::spawn(|| {
+        reserve_pool(100);
+        assert_eq!(pool_len(), 2 * Slot::CHUNK_LEN);
+        assert_eq!(stats().slots, 2 * Slot::CHUNK_LEN);
+        let mut span = Span::new();
+        let ptrs: Vec<_> = (0..2 * Slot::CHUNK_LEN).map(|i| span.alloc(i)).collect();
+        assert!(ptrs.iter().all(|ptr| ptr.generation() == 0));
+        assert_eq!(stats().slots, 2 * Slot::CHUNK_LEN);
+        assert_eq!(stats().fresh, 2 * Slot::CHUNK_LEN);
+        drop(span);
+        assert!(ptrs.iter().all(|ptr| !ptr.is_valid()));
+
+        set_pool_limit(3 * Slot::CHUNK_LEN - 10);
+        reserve_pool(2 * Slot::CHUNK_LEN);
+        assert_eq!(pool_len(), 3 * Slot::CHUNK_LEN - 10);
+        reserve_pool(1);
+        assert_eq!(stats().slots, 3 * Slot::CHUNK_LEN - 10);
+    })
+    .join()
+    .unwrap();
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn without_recycling() {
+    std::thread::spawn(|| {
+        set_pool_limit(100);
+        let mut span = Span::new();
+        let _ = span.alloc(1);
+        drop(span);
+        assert_eq!(pool_len(), Slot::CHUNK_LEN);
+
+        let mut span = Span::without_recycling();
+        let ptrs: Vec<_> = (0..10).map(|i| span.alloc(i)).collect();
+        let bulk = span.alloc_iter(0..10);
+        let mut child = span.child();
+        let child_ptr = child.alloc(5);
+        assert_eq!(pool_len(), Slot::CHUNK_LEN);
+        drop(span);
+        assert_eq!(pool_len(), Slot::CHUNK_LEN);
+        assert!(ptrs.iter().chain(&bulk).all(|ptr| !ptr.is_valid()));
+        assert!(!child_ptr.is_valid());
+        assert_eq!(ptrs[0].alloc.slot.gen.get(), RETIRED_GEN);
+        assert_eq!(stats().retired, 21);
+
+        let mut span = Span::new();
+        let ptrs: Vec<_> = (0..2 * Slot::CHUNK_LEN).map(|i| span.alloc(i)).collect();
+        drop(span);
+        assert_eq!(pool_len(), 100);
+        assert!(ptrs.iter().all(|ptr| !ptr.is_valid()));
+    })
+    .join()
+    .unwrap();
+}
+
+#[cfg(all(feature = "std", not(feature = "shared-pool")))]
+#[test]
+fn drain_recycled_pool() {
+    std::thread::spawn(|| {
+        let mut span = Span::new();
+        for i in 0..Slot::CHUNK_LEN {
+            let _ = span.alloc(i);
+        }
+        let mut partial = Span::new();
+        let ptr = partial.alloc(5);
+        drop(span);
+        assert_eq!(pool_len(), 2 * Slot::CHUNK_LEN - 1);
+
+        // SAFETY: The pointers to recycled allocations were discarded right away.
+        assert_eq!(unsafe { drain_pool() }, Slot::CHUNK_LEN);
+        assert_eq!(pool_len(), Slot::CHUNK_LEN - 1);
+        assert_eq!(*ptr.read(), 5);
+    })
+    .join()
+    .unwrap();
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn pool_allocator() {
+    use std::{
+        alloc::{GlobalAlloc, System},
+        sync::atomic::{AtomicUsize, Ordering},
+    };
+
+    struct CountingAlloc(AtomicUsize);
+
+    // SAFETY: Forwards to the system allocator.
+    unsafe impl GlobalAlloc for CountingAlloc {
+        unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+            self.0.fetch_add(layout.size(), Ordering::Relaxed);
+            // SAFETY: Guaranteed by the caller.
+            unsafe { System.alloc(layout) }
+        }
+
+        unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+            self.0.fetch_sub(layout.size(), Ordering::Relaxed);
+            // SAFETY: Guaranteed by the caller.
+            unsafe { System.dealloc(ptr, layout) }
+        }
+    }
+
+    static ALLOC: CountingAlloc = CountingAlloc(AtomicUsize::new(0));
+    let live = || ALLOC.0.load(Ordering::Relaxed);
+    let chunk = size_of::<Slot>() * Slot::CHUNK_LEN;
+
+    let pool: &'static PoolStorage = Box::leak(Box::new(PoolStorage::with_allocator(&ALLOC)));
+    let mut span = Span::new_in(pool);
+    let small = span.alloc(1u8);
+    let large = span.alloc([1u64; 8]);
+    let pinned = span.alloc_pinned(2u8);
+    assert_eq!(live(), chunk + 64 + 1);
+    assert_eq!(
+        (small.get(), large.get(), *pinned.read_pin()),
+        (1, [1; 8], 2)
+    );
+
+    assert_eq!(span.free(large), Ok([1; 8]));
+    let _ = span.alloc([2u64; 8]);
+    assert_eq!(live(), chunk + 64 + 1);
+    let values = span.into_erased();
+    assert_eq!(values.len(), 2);
+    assert_eq!(live(), chunk);
+
+    let mut span = Span::new_in(pool);
+    let _ = span.alloc_with(|| [3u64; 8]);
+    let _ = span.alloc_default::<[u64; 8]>();
+    let _ = span.alloc_iter([[4u64; 8]; 2]);
+    assert_eq!(live(), chunk + 4 * 64);
+    drop(span);
+    assert_eq!(live(), chunk);
+
+    // SAFETY: The pointers to recycled allocations are not used anymore.
+    assert_eq!(unsafe { pool.drain() }, Slot::CHUNK_LEN);
+    assert_eq!(live(), 0);
+}
+
+#[test]
+fn alloc_iter_reclaims() {
+    let pool: &'static PoolStorage = Box::leak(Box::new(PoolStorage::new()));
+    let mut span = Span::new_in(pool);
+    let ptrs = span.alloc_iter(0..Slot::CHUNK_LEN);
+    let guard = ptrs[0].read();
+    drop(span);
+    assert_eq!(pool.len(), Slot::CHUNK_LEN - 1);
+    drop(guard);
+
+    let mut span = Span::new_in(pool);
+    let _ = span.alloc_iter(0..Slot::CHUNK_LEN);
+    assert_eq!(pool.stats().slots, Slot::CHUNK_LEN);
+}
+
+#[test]
+fn reuse_policy() {
+    for (policy, reused) in [(ReusePolicy::Lifo, 1), (ReusePolicy::Fifo, 0)] {
+        let pool: &'static PoolStorage = Box::leak(Box::new(PoolStorage::new()));
+        pool.set_reuse_policy(policy);
+        let mut span = Span::new_in(pool);
+        let ptrs = span.alloc_iter(0..Slot::CHUNK_LEN);
+        assert!(pool.is_empty());
+        assert!(span.dealloc(ptrs[0]) && span.dealloc(ptrs[1]));
+        let ptr = span.alloc(0);
+        assert_eq!(ptr.into_raw().slot, ptrs[reused].into_raw().slot);
+    }
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn pool_storage() {
+    std::thread::spawn(|| {
+        let pool: &'static PoolStorage = Box::leak(Box::new(PoolStorage::new()));
+        let mut span = Span::new_in(pool);
+        let mut child = span.child();
+        let ptr = child.alloc(5);
+        let _ = span.alloc_iter(0..Slot::CHUNK_LEN);
+        assert_eq!(pool.len(), Slot::CHUNK_LEN - 1);
+        drop(span);
+        assert!(!ptr.is_valid());
+        assert_eq!(pool.len(), 2 * Slot::CHUNK_LEN);
+        assert_eq!(pool_len(), 0);
+
+        // SAFETY: The pointer to the recycled allocation is not used anymore.
+        assert_eq!(unsafe { pool.drain() }, 2 * Slot::CHUNK_LEN);
+        assert!(pool.is_empty());
+    })
+    .join()
+    .unwrap();
+}
+
+#[test]
+fn drain_keeps_listed_chunks() {
+    let pool: &'static PoolStorage = Box::leak(Box::new(PoolStorage::new()));
+    let mut span = Span::new_in(pool);
+    let ptr = span.alloc(5);
+    assert_eq!(ptr.take(), Ok(5));
+    assert_eq!(pool.len(), Slot::CHUNK_LEN);
+
+    // SAFETY: The pointer to the recycled allocation is not used anymore.
+    assert_eq!(unsafe { pool.drain() }, 0);
+    drop(span);
+    // SAFETY: As above.
+    assert_eq!(unsafe { pool.drain() }, Slot::CHUNK_LEN);
+    assert!(pool.is_empty());
+}
+
+#[cfg(all(feature = "std", not(feature = "shared-pool")))]
+#[test]
+fn span_stats() {
+    std::thread::spawn(|| {
+        let mut span = Span::new();
+        let ptr = span.alloc(5);
+        let _ = span.alloc_iter(0..3);
+        span.free(ptr).unwrap();
+        let _ = span.alloc(6);
+        assert_eq!(
+            stats(),
+            SpanStats {
+                reused: 1,
+                fresh: 4,
+                pool_len: Slot::CHUNK_LEN - 4,
+                peak_pool_len: Slot::CHUNK_LEN - 1,
+                live: 4,
+                slots: Slot::CHUNK_LEN,
+                recycled: 1,
+                retired: 0,
+            }
+        );
+        let _ = span.alloc("text");
+        assert_eq!(span.len(), 5);
+        assert_eq!(span.count_of::<i32>(), 4);
+        assert_eq!(span.count_of::<&str>(), 1);
+        drop(span);
+        assert_eq!(stats().live, 0);
+        assert_eq!(stats().recycled, 6);
+        assert_eq!(stats().peak_pool_len, Slot::CHUNK_LEN);
+
+        let mut span = Span::new();
+        let _ = span.alloc_iter(0..Slot::CHUNK_LEN + 1);
+        assert_eq!(stats().reused, 6);
+        assert_eq!(stats().slots, 2 * Slot::CHUNK_LEN);
+        drop(span);
+        assert_eq!(stats().recycled, 7 + Slot::CHUNK_LEN);
+    })
+    .join()
+    .unwrap();
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn ptr_token() {
+    fn assert_send_sync<T: Send + Sync>(_: &T) {}
+
+    let mut span = Span::new();
+    let ptr = span.alloc("test".to_string());
+    let token = ptr.to_token();
+    assert_send_sync(&token);
+    let (to_coordinator, coordinator) = std::sync::mpsc::channel();
+    let (to_owner, owner) = std::sync::mpsc::channel();
+    let coordinator = std::thread::spawn(move || {
+        let token: PtrToken<String> = coordinator.recv().unwrap();
+        assert_eq!(token.redeem().err(), Some(RedeemError::WrongThread));
+        to_owner.send(token).unwrap();
+    });
+    to_coordinator.send(token).unwrap();
+    let token = owner.recv().unwrap();
+    coordinator.join().unwrap();
+    let redeemed = token.redeem().unwrap();
+    assert_eq!(redeemed, ptr);
+    assert_eq!(*redeemed.read(), "test");
+
+    let token = ptr.to_token();
+    drop(span);
+    assert!(matches!(token.redeem(), Err(RedeemError::Stale(_))));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn global_allocs_outlive_spans() {
+    fn intern(name: &str) -> Ptr<String> {
+        global_alloc(name.to_string())
+    }
+
+    let ptr = Span::scope(|span| {
+        let mut child = span.child();
+        let _ = child.alloc(5);
+        intern("config")
+    });
+    assert_eq!(*ptr.read(), "config");
+    assert_eq!(ptr.take().unwrap(), "config");
+    assert!(!ptr.is_valid());
+    assert!(!POOL.with(|pool| pool
+        .recycled
+        .borrow()
+        .iter()
+        .any(|slot| std::ptr::eq(*slot, ptr.alloc.slot))));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn trait_object() {
+    trait Shape {
+        fn area(&self) -> f32;
+        fn scale(&mut self, factor: f32);
+    }
+
+    struct Square(f32);
+
+    impl Shape for Square {
+        fn area(&self) -> f32 {
+            self.0 * self.0
+        }
+
+        fn scale(&mut self, factor: f32) {
+            self.0 *= factor;
+        }
+    }
+
+    pointee!(dyn Shape);
+
+    let mut span = Span::new();
+    let ptr: Ptr<dyn Shape> = span.alloc_boxed(Box::new(Square(2.0)));
+    assert_eq!(ptr.read().area(), 4.0);
+    ptr.write().scale(2.0);
+    assert_eq!(ptr.read().area(), 16.0);
+    drop(span);
+    assert!(matches!(ptr.try_read(), Err(AccessError::Stale(_))));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn alloc_slice() {
+    let mut span = Span::new();
+    let slice = span.alloc_slice(vec![1, 2, 3]);
+    slice.write()[0] = 4;
+    assert_eq!(*slice.read(), [4, 2, 3]);
+    let squares = span.alloc_from_iter((1..4).map(|i| i * i));
+    assert_eq!(*squares.read(), [1, 4, 9]);
+    let filled = span.alloc_slice_fill_with(3, |i| i * 2);
+    assert_eq!(*filled.read(), [0, 2, 4]);
+    let copied = span.alloc_slice_copy(&[1u32, 2, 3]);
+    copied.write().reverse();
+    assert_eq!(*copied.read(), [3, 2, 1]);
+    let text = span.alloc_str("hello");
+    text.write().make_ascii_uppercase();
+    assert_eq!(&*text.read(), "HELLO");
+    drop(span);
+    assert!(!slice.is_valid());
+    assert!(!text.is_valid());
+    assert!(matches!(copied.try_read(), Err(AccessError::Stale(_))));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn owned() {
+    let mut span = Span::new();
+    let owned = span.alloc_owned("test".to_string());
+    let ptr = *owned;
+    let kept = span.alloc_owned(5).into_ptr();
+    owned.write().push('!');
+    assert_eq!(*owned.read(), "test!");
+    assert_eq!(span.len(), 2);
+    drop(owned);
+    assert!(!ptr.is_valid());
+    assert_eq!(span.len(), 1);
+
+    let guard = kept.read();
+    drop(span.alloc_owned(6));
+    for i in 0..100 {
+        drop(span.alloc_owned(i));
+    }
+    assert!(span.allocs.len() < 100);
+    assert_eq!(*guard, 5);
+    drop(guard);
+    let owned = span.alloc_owned(7);
+    drop(span);
+    assert!(!kept.is_valid());
+    assert!(!owned.is_valid());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn alloc_copy() {
+    let mut span = Span::new();
+    let timer = span.alloc_copy(1.5f32);
+    let pair = span.alloc_copy((1u64, 2u64));
+    timer.set(timer.get() * 2.0);
+    assert_eq!(timer.get(), 3.0);
+    assert_eq!(pair.get(), (1, 2));
+    assert_eq!(span.len(), 2);
+    drop(span);
+    assert!(!timer.is_valid());
+    assert!(matches!(timer.try_get(), Err(AccessError::Stale(_))));
+    assert!(matches!(pair.try_set((3, 4)), Err(AccessError::Stale(_))));
+
+    let mut span = Span::new();
+    let counter = span.alloc_copy(7u8);
+    assert_eq!(counter.generation(), 1);
+    assert_eq!(counter.get(), 7);
+    assert!(!pair.is_valid());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn keep_alive() {
+    let mut span = Span::new();
+    let ptr = span.alloc("test".to_string());
+    let mut child = span.child();
+    let pinned = child.alloc(5);
+    let keep_alive = ptr.keep_alive();
+    let keep_alive_2 = pinned.keep_alive();
+    let keep_alive_3 = pinned.keep_alive();
+    assert_eq!(ptr.take().err(), Some(AccessError::AlreadyBorrowed));
+
+    drop(span);
+    assert_eq!(*keep_alive.read(), "test");
+    assert!(ptr.is_valid());
+    drop(keep_alive);
+    assert!(!ptr.is_valid());
+
+    drop(child);
+    drop(keep_alive_2);
+    assert_eq!(*keep_alive_3.read(), 5);
+    let gen = pinned.generation();
+    drop(keep_alive_3);
+    assert!(matches!(
+        pinned.try_read(),
+        Err(AccessError::Stale(DanglingError { actual, .. })) if actual == gen + 1
+    ));
+    assert!(matches!(
+        pinned.try_keep_alive(),
+        Err(AccessError::Stale(_))
+    ));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn alloc_with() {
+    std::thread::spawn(|| {
+        let mut span = Span::new();
+        let ptr = span.alloc_with(|| [7u8; 4096]);
+        assert_eq!(ptr.read()[4095], 7);
+        let pool = pool_len();
+
+        let err = span.try_alloc_with(|| Err::<u8, _>("failed"));
+        assert_eq!(err.err(), Some("failed"));
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = span.alloc_with(|| -> u8 { panic!("failed") });
+        }));
+        assert!(panicked.is_err());
+        let panicked = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _ = span.alloc_with(|| -> [u8; 4096] { panic!("failed") });
+        }));
+        assert!(panicked.is_err());
+        assert_eq!(span.len(), 1);
+        assert_eq!(pool_len(), pool);
+
+        let ptr = span.try_alloc_with(|| Ok::<_, ()>(5)).unwrap();
+        assert_eq!(*ptr.read(), 5);
+        let ptr = span.alloc_default::<Vec<u8>>();
+        assert!(ptr.read().is_empty());
+        assert!(matches!(*ptr.alloc.slot.value.borrow(), Value::Inline(_)));
+
+        let large = span.alloc_with(|| [1u8; 4096]);
+        let addr = large.read().as_ptr();
+        assert!(span.dealloc(large));
+        let reused = span.alloc_with(|| [2u8; 4096]);
+        assert_eq!(reused.read().as_ptr(), addr);
+    })
+    .join()
+    .unwrap();
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn append() {
+    let mut span = Span::new();
+    let mut other = Span::new();
+    let ptr = other.alloc(5);
+    let mut child = other.child();
+    let child_ptr = child.alloc(6);
+    span.append(&mut other);
+    assert!(other.is_empty());
+    drop(other);
+    assert_eq!(*ptr.read(), 5);
+    assert_eq!(span.len(), 1);
+    drop(span);
+    assert!(!ptr.is_valid());
+    assert!(!child_ptr.is_valid());
+}
+
+#[cfg(all(feature = "std", not(feature = "shared-pool")))]
+#[test]
+fn alloc_iter() {
+    std::thread::spawn(|| {
+        let mut span = Span::new();
+        let _ = span.alloc_iter(0..100);
+        assert_eq!(pool_len(), 2 * Slot::CHUNK_LEN - 100);
+        span.clear();
+        let expected: Vec<_> = POOL.with(|pool| {
+            pool.recycled
+                .borrow()
+                .iter()
+                .rev()
+                .take(100)
+                .copied()
+                .collect()
+        });
+        let ptrs = span.alloc_iter(0..100);
+        for (ptr, slot) in ptrs.iter().zip(expected) {
+            assert!(std::ptr::eq(ptr.alloc.slot, slot));
+            assert_eq!(ptr.generation(), 1);
+        }
+        assert_eq!(*ptrs[99].read(), 99);
+        assert_eq!(span.len(), 100);
+        let copies = ptrs.clone();
+        drop(span);
+        assert!(copies.iter().all(|ptr| !ptr.is_valid()));
+    })
+    .join()
+    .unwrap();
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn absorb() {
+    let mut span = Span::new();
+    let mut scratch = Span::new();
+    let ptr = scratch.alloc(5);
+    let gen = ptr.generation();
+    span.absorb(scratch);
+    assert_eq!(*ptr.read(), 5);
+    assert_eq!(ptr.generation(), gen);
+    drop(span);
+    assert!(!ptr.is_valid());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn dealloc() {
+    let drops = Rc::new(Cell::new(0));
+    let mut span = Span::new();
+    let mut other = Span::new();
+    let ptr = span.alloc(DropCounter(drops.clone()));
+    let copy = ptr;
+    let _ = span.alloc(5);
+    assert!(!other.dealloc(ptr));
+    assert!(span.dealloc(ptr));
+    assert_eq!(drops.get(), 1);
+    assert!(!copy.is_valid());
+    assert!(!span.dealloc(copy));
+    assert_eq!(span.len(), 1);
+    let gen = ptr.alloc.slot.gen.get();
+    drop(span);
+    assert_eq!(ptr.alloc.slot.gen.get(), gen);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn swap() {
+    struct Buffer(Vec<u8>);
+
+    let mut span = Span::new();
+    let front = span.alloc(Buffer(vec![1]));
+    let back = span.alloc(Buffer(vec![2]));
+    front.swap(back);
+    assert_eq!(front.read().0, [2]);
+    assert_eq!(back.read().0, [1]);
+    front.swap(front);
+    assert_eq!(front.read().0, [2]);
+
+    let guard = back.read();
+    assert_eq!(front.try_swap(back), Err(AccessError::AlreadyBorrowed));
+    drop(guard);
+    let stale = Span::new().alloc(Buffer(Vec::new()));
+    assert!(matches!(front.try_swap(stale), Err(AccessError::Stale(_))));
+    assert_eq!(front.read().0, [2]);
+}
+
+#[cfg(all(feature = "std", not(feature = "shared-pool")))]
+#[test]
+#[should_panic(expected = "generation 1 (the allocation was freed, it's empty)")]
+fn swap_stale_other_panic() {
+    let mut span = Span::new();
+    let ptr = span.alloc(5u16);
+    let stale = Span::new().alloc(6u16);
+    ptr.swap(stale);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn get_cloned() {
+    let mut span = Span::new();
+    let ptr = span.alloc(vec![1]);
+    let mut value = ptr.get_cloned();
+    ptr.write().push(2);
+    value.push(3);
+    assert_eq!(*ptr.read(), [1, 2]);
+    assert_eq!(value, [1, 3]);
+
+    let guard = ptr.read();
+    let write = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| drop(ptr.write())));
+    assert!(write.is_err());
+    drop(guard);
+
+    let copy = span.alloc(5);
+    assert_eq!(copy.get(), 5);
+    drop(span);
+    assert!(matches!(ptr.try_get_cloned(), Err(AccessError::Stale(_))));
+    assert!(matches!(copy.try_get(), Err(AccessError::Stale(_))));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn take_value() {
+    let mut span = Span::new();
+    let ptr = span.alloc("test".to_string());
+    assert_eq!(ptr.take_value().as_deref(), Some("test"));
+    assert!(ptr.is_valid());
+    assert_eq!(ptr.take_value(), None);
+    assert!(matches!(
+        ptr.try_read(),
+        Err(AccessError::TypeMismatch { actual: None, .. })
+    ));
+    ptr.set("refilled".to_string());
+    assert_eq!(*ptr.read(), "refilled");
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn guard_outlives_span() {
+    std::thread::spawn(|| {
+        let drops = Rc::new(Cell::new(0));
+        let mut span = Span::new();
+        let ptr = span.alloc(DropCounter(drops.clone()));
+        let guard = ptr.read();
+        drop(span);
+        assert!(!ptr.is_valid());
+        assert_eq!(drops.get(), 0);
+
+        let mut span = Span::new();
+        let others: Vec<_> = (0..2 * Slot::CHUNK_LEN).map(|i| span.alloc(i)).collect();
+        assert!(others
+            .iter()
+            .all(|other| !std::ptr::eq(other.alloc.slot, ptr.alloc.slot)));
+        assert_eq!(guard.0.get(), 0);
+
+        drop(guard);
+        span.clear();
+        assert_eq!(drops.get(), 1);
+        let _ = span.alloc_iter(0..pool_len());
+        assert!(span
+            .iter_of::<usize>()
+            .any(|other| std::ptr::eq(other.alloc.slot, ptr.alloc.slot)));
+    })
+    .join()
+    .unwrap();
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn transfer() {
+    let mut dst = Span::new();
+    let mut span = Span::new();
+    let ptr = span.alloc(5);
+    span.transfer(ptr, &mut dst).unwrap();
+    assert_eq!(span.transfer(ptr, &mut dst), Err(TransferError::Foreign));
+    drop(span);
+    assert_eq!(*ptr.read(), 5);
+    assert_eq!(dst.len(), 1);
+    dst.clear();
+    assert!(matches!(
+        dst.transfer(ptr, &mut Span::new()),
+        Err(TransferError::Stale(_))
+    ));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn iter_of() {
+    let mut span = Span::new();
+    let a = span.alloc(1);
+    let _ = span.alloc("test");
+    let b = span.alloc(2);
+    let c = span.alloc(3);
+    span.free(b).unwrap();
+    let _guard = c.write();
+    assert!(span.iter_of::<i32>().eq([a]));
+    assert_eq!(span.iter_of::<&str>().count(), 1);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn type_mismatch() {
+    let mut span = Span::new();
+    let ptr = span.alloc(5u32);
+    let wrong = Ptr::<String> {
+        alloc: ptr.alloc,
+        _marker: PhantomData,
+    };
+    assert_eq!(
+        wrong.try_write().err(),
+        Some(AccessError::TypeMismatch {
+            expected: TypeId::of::<String>(),
+            actual: Some(TypeId::of::<u32>()),
+        })
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn span_debug() {
+    let mut span = Span::with_capacity(4);
+    let _ = span.alloc(5);
+    assert_eq!(format!("{span:?}"), "Span { len: 1, capacity: 4, .. }");
+    let span = span.with_name("test");
+    assert_eq!(
+        format!("{span:?}"),
+        r#"Span { name: "test", len: 1, capacity: 4, .. }"#
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn span_debug_alternate() {
+    let mut span = Span::with_capacity(4);
+    let int = span.alloc(5);
+    let text = span.alloc_str("text");
+    let _write = text.write();
+    assert_eq!(
+        format!("{span:#?}").split_whitespace().collect::<String>(),
+        format!(
+            "Span{{len:2,capacity:4,allocs:[i32{{gen:{},borrow:Unborrowed,}},str{{gen:{},\
+             borrow:Exclusive,}},],..}}",
+            int.generation(),
+            text.generation()
+        )
+    );
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn erased_ptr() {
+    let mut span = Span::new();
+    let int = span.alloc(5);
+    let string = span.alloc("test".to_string());
+    let erased = [int.erase(), ErasedPtr::from(string)];
+    assert_eq!(erased[0].type_id(), Some(TypeId::of::<i32>()));
+    assert!(erased[0].downcast::<i32>().unwrap() == int);
+    assert!(erased[0].downcast::<String>().is_none());
+    assert_eq!(*erased[1].downcast::<String>().unwrap().read(), "test");
+    assert!(erased[0] == ErasedPtr::from(int));
+    assert!(erased[0] != erased[1]);
+    drop(span);
+    assert_eq!(erased[0].type_id(), None);
+    assert!(erased[0].downcast::<i32>().is_none());
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn with() {
+    let mut span = Span::new();
+    let ptr = span.alloc("test".to_string());
+    assert_eq!(ptr.with(String::len), 4);
+    ptr.with_mut(|v| v.push('!'));
+    assert_eq!(ptr.try_with(|v| v.clone()).unwrap(), "test!");
+    drop(span);
+    assert!(matches!(
+        ptr.try_with_mut(|_| ()),
+        Err(AccessError::Stale(_))
+    ));
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn nested_with() {
+    let mut span = Span::new();
+    let a = span.alloc(2);
+    let b = span.alloc(3);
+    assert_eq!(a.with(|a| b.with(|b| a * b)), 6);
+    assert_eq!(a.with(|x| a.with(|y| x + y)), 4);
+    a.with_mut(|a| b.with(|b| *a += b));
+    assert_eq!(a.get(), 5);
+
+    let nested = a.with_mut(|_| a.try_with_mut(|_| ()));
+    assert_eq!(nested, Err(AccessError::AlreadyBorrowed));
+    assert_eq!(
+        a.with(|_| a.try_with_mut(|_| ())),
+        Err(AccessError::AlreadyBorrowed)
+    );
+    assert!(a.try_with_mut(|a| *a += 1).is_ok());
+    assert_eq!(a.get(), 6);
+}
+
+#[cfg(feature = "std")]
+#[test]
+fn projected_ptr() {
+    struct Inventory {
+        items: Vec<u32>,
+    }
+
+    struct Player {
+        inventory: Inventory,
+    }
+
+    let mut span = Span::new();
+    let player = span.alloc(Player {
+        inventory: Inventory { items: vec![1] },
+    });
+    let items = player
+        .map(|p| &p.inventory, |p| &mut p.inventory)
+        .map(|i| &i.items, |i| &mut i.items);
+    let copy = items;
+    copy.write().push(2);
+    assert_eq!(player.read().inventory.items, [1, 2]);
+    assert_eq!(*items.read(), [1, 2]);
+    drop(span);
+    assert!(!items.is_valid());
+    assert!(matches!(items.try_read(), Err(AccessError::Stale(_))));
+}
+
+#[cfg(feature = "std")]
+#[test]
+#[should_panic(expected = "ProjectedPtr<u8>: value is already borrowed")]
+fn projected_ptr_borrowed_panic() {
+    let mut span = Span::new();
+    let pair = span.alloc((5u8, 6u16));
+    let first = pair.map(|p| &p.0, |p| &mut p.0);
+    let _guard = pair.read();
+    let _ = first.write();
+}
+
+#[cfg(feature = "shared-pool")]
+#[test]
+fn shared_pool() {
+    let slots: BTreeSet<usize> = std::thread::spawn(|| {
+        let mut span = Span::new();
+        let ptrs = span.alloc_iter(0..32 * Slot::CHUNK_LEN);
+        ptrs.iter()
+            .map(|ptr| core::ptr::from_ref(ptr.alloc.slot) as usize)
+            .collect()
+    })
+    .join()
+    .unwrap();
+    std::thread::spawn(move || {
+        // Other tests may hand over slots concurrently, so some of them may be taken first.
+        let mut span = Span::new();
+        let ptrs = span.alloc_iter(0..32 * Slot::CHUNK_LEN);
+        assert!(ptrs
+            .iter()
+            .any(|ptr| slots.contains(&(core::ptr::from_ref(ptr.alloc.slot) as usize))));
+        assert!(ptrs.iter().all(|ptr| ptr.generation() > 0));
+        assert_eq!(stats().fresh, 0);
+    })
+    .join()
+    .unwrap();
 }
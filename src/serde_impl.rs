@@ -0,0 +1,478 @@
+//! [`Serialize`] and [`Deserialize`] implementations, requires the `serde` feature.
+//!
+//! A [`Ptr`] serializes as the value it points to. Deserializing one allocates the value in the
+//! [`Span`] passed to [`Span::deserialize_in`], so that pointers nested in other values can be
+//! deserialized too.
+//!
+//! Pointers aren't deduplicated: a value pointed to by several serialized pointers is
+//! serialized once per pointer and deserialized into separate allocations.
+//! To keep shared and cyclic pointers intact, snapshot whole spans with a [`SpanSerializer`].
+
+use std::{
+    any::{Any, TypeId},
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    marker::PhantomData,
+};
+
+use serde::{
+    de::{DeserializeOwned, DeserializeSeed, Error as _, SeqAccess, Visitor},
+    ser::{Error as _, SerializeSeq},
+    Deserialize, Deserializer, Serialize, Serializer,
+};
+
+use crate::{Alloc, Pointee, Ptr, Slot, Span};
+
+impl Span {
+    /// Runs `f` with this [`Span`] as the target of deserialized pointers.
+    ///
+    /// # Panics
+    /// Panics if called from within the [`Drop`] of a value held by this [`Span`].
+    pub fn deserialize_in<R>(&mut self, f: impl FnOnce() -> R) -> R {
+        /// Gives the [`Span`] back even if `f` panics.
+        struct Restore<'a> {
+            span: &'a mut Span,
+            prev: Option<Span>,
+        }
+
+        impl Drop for Restore<'_> {
+            fn drop(&mut self) {
+                let span = DESERIALIZE_SPAN.replace(self.prev.take());
+                *self.span = span.expect("deserialization span is set");
+            }
+        }
+
+        let prev = DESERIALIZE_SPAN.replace(Some(std::mem::take(self)));
+        let _restore = Restore { span: self, prev };
+        f()
+    }
+
+    /// Deserializes a `T` and allocates it in this [`Span`].
+    ///
+    /// Pointers nested in `T` are allocated in this [`Span`] as well.
+    pub fn deserialize_alloc<'de, T, D>(&mut self, deserializer: D) -> Result<Ptr<T>, D::Error>
+    where
+        T: Deserialize<'de> + 'static,
+        D: Deserializer<'de>,
+    {
+        self.deserialize_in(|| Ptr::deserialize(deserializer))
+    }
+}
+
+/// Serializes the pointed-to value, failing if the pointer can't be read.
+///
+/// Within [`SpanSerializer::serialize`] the pointer is serialized as the index of its allocation
+/// instead.
+impl<T: ?Sized + Pointee + Serialize> Serialize for Ptr<T> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let index = SNAPSHOT.with(|snapshot| match &*snapshot.borrow() {
+            Some(Snapshot::Serialize(indices)) => Some(indices.get(&self.alloc.key()).copied()),
+            _ => None,
+        });
+        if let Some(index) = index {
+            let index = index.ok_or_else(|| {
+                S::Error::custom("pointer doesn't point into the serialized span")
+            })?;
+            return serializer.serialize_u64(index as u64);
+        }
+        self.try_read()
+            .map_err(S::Error::custom)?
+            .serialize(serializer)
+    }
+}
+
+/// Allocates the deserialized value in the [`Span`] passed to [`Span::deserialize_in`], failing
+/// outside of it.
+///
+/// Within [`SpanSerializer::deserialize`] the pointer is deserialized from the index of its
+/// allocation instead.
+impl<'de, T: Deserialize<'de> + 'static> Deserialize<'de> for Ptr<T> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let restoring =
+            SNAPSHOT.with(|snapshot| matches!(*snapshot.borrow(), Some(Snapshot::Deserialize(_))));
+        if restoring {
+            let index = usize::deserialize(deserializer)?;
+            return Ok(Ptr {
+                alloc: Restored::with(|restored| restored.alloc(index)),
+                _marker: PhantomData,
+            });
+        }
+        let value = T::deserialize(deserializer)?;
+        DESERIALIZE_SPAN.with(|span| match span.borrow_mut().as_mut() {
+            Some(span) => Ok(span.alloc(value)),
+            None => Err(D::Error::custom(
+                "pointers can only be deserialized within `Span::deserialize_in`",
+            )),
+        })
+    }
+}
+
+/// Registry of the types that can be snapshotted along with a [`Span`].
+///
+/// A [`Span`] is serialized as a sequence of its live allocations, in allocation order, each
+/// tagged with the registered name of its type. Pointers into the [`Span`] are serialized as the
+/// index of the allocation they point to, see [`Span::index_of`], and deserialized as pointers to
+/// the corresponding allocation of the restored [`Span`], see [`Span::ptr_at`].
+/// This keeps shared and cyclic pointers intact.
+///
+/// ```
+/// use genalloc::{Ptr, Span, SpanSerializer};
+///
+/// #[derive(serde::Serialize, serde::Deserialize)]
+/// struct Player {
+///     name: String,
+///     target: Option<Ptr<Player>>,
+/// }
+///
+/// let mut serializer = SpanSerializer::new();
+/// serializer.register::<Player>();
+///
+/// let mut span = Span::new();
+/// let alice = span.alloc(Player { name: "alice".to_string(), target: None });
+/// let bob = span.alloc(Player { name: "bob".to_string(), target: Some(alice) });
+/// alice.write().target = Some(bob);
+///
+/// let mut json = Vec::new();
+/// serializer.serialize(&span, &mut serde_json::Serializer::new(&mut json)).unwrap();
+/// let index = span.index_of(bob).unwrap();
+///
+/// let restored = serializer.deserialize(&mut serde_json::Deserializer::from_slice(&json)).unwrap();
+/// let bob = restored.ptr_at(index).unwrap().downcast::<Player>().unwrap();
+/// let alice = bob.read().target.unwrap();
+/// assert_eq!(alice.read().name, "alice");
+/// assert!(alice.read().target == Some(bob));
+/// ```
+#[derive(Default)]
+pub struct SpanSerializer {
+    by_type: HashMap<TypeId, Registration>,
+    by_name: HashMap<&'static str, TypeId>,
+}
+
+impl SpanSerializer {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Registers `T` under its [`type_name`](std::any::type_name).
+    ///
+    /// Type names aren't guaranteed to be stable across compiler versions, use
+    /// [`SpanSerializer::register_as`] for snapshots that need to outlive the binary.
+    pub fn register<T: Serialize + DeserializeOwned + 'static>(&mut self) {
+        self.register_as::<T>(std::any::type_name::<T>());
+    }
+
+    /// Registers `T` under `name`, replacing any type previously registered under it.
+    pub fn register_as<T: Serialize + DeserializeOwned + 'static>(&mut self, name: &'static str) {
+        let registration = Registration {
+            name,
+            serialize: |any| {
+                any.downcast_ref::<T>()
+                    .expect("registrations are keyed by their type")
+            },
+            deserialize: |deserializer| Ok(Box::new(erased_serde::deserialize::<T>(deserializer)?)),
+        };
+        if let Some(old) = self.by_type.insert(TypeId::of::<T>(), registration) {
+            self.by_name.remove(old.name);
+        }
+        if let Some(old) = self.by_name.insert(name, TypeId::of::<T>()) {
+            if old != TypeId::of::<T>() {
+                self.by_type.remove(&old);
+            }
+        }
+    }
+
+    /// Serializes the live allocations of `span`.
+    ///
+    /// Fails if a value's type isn't registered, a value is currently mutably borrowed, or a
+    /// pointer points outside of `span`, e.g. into one of its children.
+    pub fn serialize<S: Serializer>(&self, span: &Span, serializer: S) -> Result<S::Ok, S::Error> {
+        let allocs: Vec<Alloc> = span.live_allocs().collect();
+        let indices = allocs
+            .iter()
+            .enumerate()
+            .map(|(index, alloc)| (alloc.key(), index))
+            .collect();
+        let _restore = Snapshot::set(Snapshot::Serialize(indices));
+        let mut seq = serializer.serialize_seq(Some(allocs.len()))?;
+        for alloc in allocs {
+            let value = alloc
+                .slot
+                .value
+                .try_borrow()
+                .map_err(|_| S::Error::custom(crate::AccessError::AlreadyBorrowed))?;
+            let value = value.as_deref().expect("live allocation holds a value");
+            let registration = self
+                .by_type
+                .get(&value.type_id())
+                .ok_or_else(|| S::Error::custom("span holds a value of an unregistered type"))?;
+            seq.serialize_element(&(registration.name, (registration.serialize)(value)))?;
+        }
+        seq.end()
+    }
+
+    /// Deserializes a [`Span`] serialized by [`SpanSerializer::serialize`].
+    ///
+    /// Fails if a value's type isn't registered or a pointer points outside of the [`Span`].
+    pub fn deserialize<'de, D: Deserializer<'de>>(
+        &self,
+        deserializer: D,
+    ) -> Result<Span, D::Error> {
+        let _restore = Snapshot::set(Snapshot::Deserialize(Restored::default()));
+        let len = deserializer.deserialize_seq(SpanVisitor(self))?;
+        let restored = SNAPSHOT.with(|snapshot| match snapshot.borrow_mut().as_mut() {
+            Some(Snapshot::Deserialize(restored)) => std::mem::take(restored),
+            _ => unreachable!("snapshot is restored until `_restore` drops"),
+        });
+        restored
+            .into_span(len)
+            .ok_or_else(|| D::Error::custom("pointer doesn't point into the deserialized span"))
+    }
+}
+
+struct Registration {
+    name: &'static str,
+    serialize: fn(&dyn Any) -> &dyn erased_serde::Serialize,
+    deserialize: fn(&mut dyn erased_serde::Deserializer) -> erased_serde::Result<Box<dyn Any>>,
+}
+
+/// Visits the allocations of a serialized [`Span`], returning their number.
+struct SpanVisitor<'a>(&'a SpanSerializer);
+
+impl<'de> Visitor<'de> for SpanVisitor<'_> {
+    type Value = usize;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a sequence of allocations")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<usize, A::Error> {
+        let mut len = 0;
+        while let Some(value) = seq.next_element_seed(AllocSeed(self.0))? {
+            Restored::with(|restored| {
+                let alloc = restored.alloc(len);
+                *alloc.slot.value.borrow_mut() = value.into();
+            });
+            len += 1;
+        }
+        Ok(len)
+    }
+}
+
+/// Deserializes a `(type name, value)` pair.
+struct AllocSeed<'a>(&'a SpanSerializer);
+
+impl<'de> DeserializeSeed<'de> for AllocSeed<'_> {
+    type Value = Box<dyn Any>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        deserializer.deserialize_tuple(2, self)
+    }
+}
+
+impl<'de> Visitor<'de> for AllocSeed<'_> {
+    type Value = Box<dyn Any>;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a type name and a value")
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(self, mut seq: A) -> Result<Self::Value, A::Error> {
+        let name: String = seq
+            .next_element()?
+            .ok_or_else(|| A::Error::invalid_length(0, &self))?;
+        let registration = self
+            .0
+            .by_name
+            .get(name.as_str())
+            .and_then(|type_id| self.0.by_type.get(type_id))
+            .ok_or_else(|| A::Error::custom(format!("unregistered type `{name}`")))?;
+        seq.next_element_seed(ValueSeed(registration))?
+            .ok_or_else(|| A::Error::invalid_length(1, &self))
+    }
+}
+
+/// Deserializes a value of a registered type.
+struct ValueSeed<'a>(&'a Registration);
+
+impl<'de> DeserializeSeed<'de> for ValueSeed<'_> {
+    type Value = Box<dyn Any>;
+
+    fn deserialize<D: Deserializer<'de>>(self, deserializer: D) -> Result<Self::Value, D::Error> {
+        let mut deserializer = <dyn erased_serde::Deserializer>::erase(deserializer);
+        (self.0.deserialize)(&mut deserializer).map_err(D::Error::custom)
+    }
+}
+
+/// State of an ongoing [`SpanSerializer`] operation.
+enum Snapshot {
+    /// Indices of the allocations being serialized, keyed by [`Alloc::key`].
+    Serialize(HashMap<(*const Slot, u32), usize>),
+    Deserialize(Restored),
+}
+
+impl Snapshot {
+    /// Sets the current thread's snapshot, restoring the previous one once the returned guard
+    /// drops.
+    fn set(snapshot: Snapshot) -> impl Drop {
+        struct Restore(Option<Snapshot>);
+
+        impl Drop for Restore {
+            fn drop(&mut self) {
+                SNAPSHOT.set(self.0.take());
+            }
+        }
+
+        Restore(SNAPSHOT.replace(Some(snapshot)))
+    }
+}
+
+/// Allocations of a [`Span`] being deserialized.
+///
+/// Pointers may be deserialized before the allocation they point to, so allocations are created
+/// on first use, keyed by their index.
+#[derive(Default)]
+struct Restored {
+    span: Span,
+    allocs: HashMap<usize, Alloc>,
+}
+
+impl Restored {
+    fn with<R>(f: impl FnOnce(&mut Restored) -> R) -> R {
+        SNAPSHOT.with(|snapshot| match snapshot.borrow_mut().as_mut() {
+            Some(Snapshot::Deserialize(restored)) => f(restored),
+            _ => unreachable!("only called while deserializing a snapshot"),
+        })
+    }
+
+    fn alloc(&mut self, index: usize) -> Alloc {
+        *self
+            .allocs
+            .entry(index)
+            .or_insert_with(|| self.span.alloc_any(crate::Value::Empty, None))
+    }
+
+    /// Orders the allocations by index, returning [`None`] if a pointer's index is out of range.
+    fn into_span(mut self, len: usize) -> Option<Span> {
+        if self.allocs.len() != len {
+            return None;
+        }
+        let allocs = (0..len)
+            .map(|index| self.allocs.get(&index).copied())
+            .collect::<Option<_>>()?;
+        self.span.allocs = allocs;
+        Some(self.span)
+    }
+}
+
+thread_local! {
+    static DESERIALIZE_SPAN: RefCell<Option<Span>> = const { RefCell::new(None) };
+    static SNAPSHOT: RefCell<Option<Snapshot>> = const { RefCell::new(None) };
+}
+
+#[test]
+fn round_trip() {
+    #[derive(Serialize, Deserialize)]
+    struct Node {
+        name: String,
+        children: Vec<Ptr<Node>>,
+    }
+
+    let mut span = Span::new();
+    let leaf = span.alloc(Node {
+        name: "leaf".to_string(),
+        children: Vec::new(),
+    });
+    let root = span.alloc(Node {
+        name: "root".to_string(),
+        children: vec![leaf, leaf],
+    });
+    let json = serde_json::to_string(&root).unwrap();
+
+    let mut other = Span::new();
+    let copy: Ptr<Node> = other
+        .deserialize_alloc(&mut serde_json::Deserializer::from_str(&json))
+        .unwrap();
+    assert_eq!(other.len(), 3);
+    let copy = copy.read();
+    assert_eq!(copy.name, "root");
+    assert_eq!(copy.children[0].read().name, "leaf");
+    assert!(copy.children[0] != copy.children[1]);
+
+    drop(span);
+    assert!(serde_json::to_string(&root).is_err());
+}
+
+#[test]
+fn deserialize_outside_span() {
+    assert!(serde_json::from_str::<Ptr<u32>>("5").is_err());
+}
+
+#[test]
+fn snapshot_round_trip() {
+    #[derive(Serialize, Deserialize)]
+    struct Node {
+        name: String,
+        next: Option<Ptr<Node>>,
+    }
+
+    let mut serializer = SpanSerializer::new();
+    serializer.register::<Node>();
+    serializer.register_as::<u32>("u32");
+
+    let mut span = Span::new();
+    let freed = span.alloc(5u32);
+    let a = span.alloc(Node {
+        name: "a".to_string(),
+        next: None,
+    });
+    let b = span.alloc(Node {
+        name: "b".to_string(),
+        next: Some(a),
+    });
+    a.write().next = Some(a);
+    let _ = span.alloc(6u32);
+    span.free(freed).unwrap();
+
+    let json = {
+        let mut json = Vec::new();
+        serializer
+            .serialize(&span, &mut serde_json::Serializer::new(&mut json))
+            .unwrap();
+        json
+    };
+    let index = span.index_of(b).unwrap();
+    drop(span);
+
+    let restored = serializer
+        .deserialize(&mut serde_json::Deserializer::from_slice(&json))
+        .unwrap();
+    assert_eq!(restored.len(), 3);
+    let b = restored.ptr_at(index).unwrap().downcast::<Node>().unwrap();
+    let a = b.read().next.unwrap();
+    assert_eq!(a.read().name, "a");
+    assert!(a.read().next == Some(a));
+    let six = restored.ptr_at(2).unwrap().downcast::<u32>().unwrap();
+    assert_eq!(*six.read(), 6);
+}
+
+#[test]
+fn snapshot_errors() {
+    let mut serializer = SpanSerializer::new();
+    let mut span = Span::new();
+    let mut other = Span::new();
+    let _ = span.alloc(other.alloc(5u32));
+    let mut json = Vec::new();
+    let mut to_json = |serializer: &SpanSerializer, span: &Span| {
+        serializer.serialize(span, &mut serde_json::Serializer::new(&mut json))
+    };
+    assert!(to_json(&serializer, &span).is_err());
+    serializer.register_as::<Ptr<u32>>("ptr");
+    assert!(to_json(&serializer, &span).is_err());
+
+    let from_json =
+        |json: &str| serializer.deserialize(&mut serde_json::Deserializer::from_str(json));
+    assert!(from_json(r#"[["ptr", 0]]"#).is_ok());
+    assert!(from_json(r#"[["ptr", 1]]"#).is_err());
+    assert!(from_json(r#"[["u64", 5]]"#).is_err());
+}
@@ -0,0 +1,338 @@
+//! Thread-safe generational allocations.
+//!
+//! [`SyncSpan`] and [`SyncPtr<T>`] mirror [`Span`](crate::Span) and [`Ptr<T>`](crate::Ptr),
+//! but their allocations are guarded by [`RwLock`]s and recycled through a global pool, so
+//! pointers can be sent to and dereferenced from other threads.
+//! This requires the pointed-to values to be [`Send`] and [`Sync`].
+//!
+//! Requires the `sync` feature.
+
+use std::{
+    any::{Any, TypeId},
+    marker::PhantomData,
+    ops::{Deref, DerefMut},
+    sync::{
+        atomic::{AtomicU32, Ordering},
+        Mutex, PoisonError, RwLock, RwLockReadGuard, RwLockWriteGuard, TryLockError,
+    },
+};
+
+use crate::{AccessError, DanglingError, RETIRED_GEN};
+
+type Value = Option<Box<dyn Any + Send + Sync>>;
+
+/// Thread-safe generational allocations span.
+#[derive(Default)]
+pub struct SyncSpan(Vec<SyncAlloc>);
+
+impl SyncSpan {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Allocates `v` on the heap and stores the pointer in a generational allocation.
+    ///
+    /// Then the [`SyncSpan`] gets dropped it recycles it's generational allocations so that they
+    /// can be reused by other spans on any thread.
+    ///
+    /// The returned [`SyncPtr<T>`] is [`Copy`] even if the underlying `T` is not [`Copy`].
+    /// This pointer gets invalidated whenever it's [`SyncSpan`] is dropped.
+    #[must_use]
+    pub fn alloc<T: Send + Sync + 'static>(&mut self, v: T) -> SyncPtr<T> {
+        let slot = RECYCLED_SYNC_ALLOCS
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .pop()
+            .unwrap_or_else(SyncSlot::leak);
+        *slot.value.write().unwrap_or_else(PoisonError::into_inner) = Some(Box::new(v));
+        let alloc = SyncAlloc {
+            slot,
+            gen: slot.gen.load(Ordering::Acquire),
+        };
+        self.0.push(alloc);
+        SyncPtr {
+            alloc,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Recycles all allocations of this [`SyncSpan`] while keeping it around for reuse.
+    ///
+    /// Blocks until all guards to the allocations are released.
+    pub fn clear(&mut self) {
+        for alloc in self.0.drain(..) {
+            alloc.recycle();
+        }
+    }
+}
+
+impl Drop for SyncSpan {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+/// Thread-safe generational pointer.
+///
+/// [`SyncPtr<T>`] is [`Copy`] even if the underlying `T` is not [`Copy`].
+pub struct SyncPtr<T> {
+    alloc: SyncAlloc,
+    _marker: PhantomData<T>,
+}
+
+impl<T> Clone for SyncPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for SyncPtr<T> {}
+
+impl<T: Send + Sync + 'static> SyncPtr<T> {
+    /// Returns `true` if the [`SyncSpan`] owning the allocation is still alive.
+    pub fn is_valid(&self) -> bool {
+        self.alloc.gen == self.alloc.slot.gen.load(Ordering::Acquire)
+    }
+
+    /// Immutably locks the pointed-to value, blocking until it's available.
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale.
+    /// See [`SyncPtr::try_read`] for a non-panicking variant.
+    pub fn read(&self) -> SyncRef<T> {
+        let guard = self
+            .alloc
+            .slot
+            .value
+            .read()
+            .unwrap_or_else(PoisonError::into_inner);
+        match SyncRef::new(self.alloc, guard) {
+            Ok(guard) => guard,
+            Err(err) => err.panic::<T>("SyncPtr", ""),
+        }
+    }
+
+    /// Mutably locks the pointed-to value, blocking until it's available.
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale.
+    /// See [`SyncPtr::try_write`] for a non-panicking variant.
+    pub fn write(&self) -> SyncRefMut<T> {
+        let guard = self
+            .alloc
+            .slot
+            .value
+            .write()
+            .unwrap_or_else(PoisonError::into_inner);
+        match SyncRefMut::new(self.alloc, guard) {
+            Ok(guard) => guard,
+            Err(err) => err.panic::<T>("SyncPtr", ""),
+        }
+    }
+
+    /// Immutably locks the pointed-to value, returning an error if it's not available right
+    /// away.
+    pub fn try_read(&self) -> Result<SyncRef<T>, AccessError> {
+        let guard = match self.alloc.slot.value.try_read() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+            Err(TryLockError::WouldBlock) => return Err(AccessError::AlreadyBorrowed),
+        };
+        SyncRef::new(self.alloc, guard)
+    }
+
+    /// Mutably locks the pointed-to value, returning an error if it's not available right away.
+    pub fn try_write(&self) -> Result<SyncRefMut<T>, AccessError> {
+        let guard = match self.alloc.slot.value.try_write() {
+            Ok(guard) => guard,
+            Err(TryLockError::Poisoned(err)) => err.into_inner(),
+            Err(TryLockError::WouldBlock) => return Err(AccessError::AlreadyBorrowed),
+        };
+        SyncRefMut::new(self.alloc, guard)
+    }
+}
+
+/// Shared lock guard returned by [`SyncPtr::read`].
+pub struct SyncRef<T> {
+    guard: RwLockReadGuard<'static, Value>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: 'static> SyncRef<T> {
+    fn new(alloc: SyncAlloc, guard: RwLockReadGuard<'static, Value>) -> Result<Self, AccessError> {
+        alloc.check()?;
+        if !guard.as_deref().is_some_and(|any| any.is::<T>()) {
+            return Err(AccessError::TypeMismatch {
+                expected: TypeId::of::<T>(),
+                actual: guard.as_deref().map(|any| any.type_id()),
+            });
+        }
+        Ok(Self {
+            guard,
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<T: 'static> Deref for SyncRef<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard
+            .as_deref()
+            .and_then(|any| any.downcast_ref())
+            .expect("value was checked to be of type `T`")
+    }
+}
+
+/// Exclusive lock guard returned by [`SyncPtr::write`].
+pub struct SyncRefMut<T> {
+    guard: RwLockWriteGuard<'static, Value>,
+    _marker: PhantomData<T>,
+}
+
+impl<T: 'static> SyncRefMut<T> {
+    fn new(alloc: SyncAlloc, guard: RwLockWriteGuard<'static, Value>) -> Result<Self, AccessError> {
+        alloc.check()?;
+        if !guard.as_deref().is_some_and(|any| any.is::<T>()) {
+            return Err(AccessError::TypeMismatch {
+                expected: TypeId::of::<T>(),
+                actual: guard.as_deref().map(|any| any.type_id()),
+            });
+        }
+        Ok(Self {
+            guard,
+            _marker: PhantomData,
+        })
+    }
+}
+
+impl<T: 'static> Deref for SyncRefMut<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.guard
+            .as_deref()
+            .and_then(|any| any.downcast_ref())
+            .expect("value was checked to be of type `T`")
+    }
+}
+
+impl<T: 'static> DerefMut for SyncRefMut<T> {
+    fn deref_mut(&mut self) -> &mut T {
+        self.guard
+            .as_deref_mut()
+            .and_then(|any| any.downcast_mut())
+            .expect("value was checked to be of type `T`")
+    }
+}
+
+/// Thread-safe generational allocation.
+#[derive(Clone, Copy)]
+struct SyncAlloc {
+    slot: &'static SyncSlot,
+    gen: u32,
+}
+
+impl SyncAlloc {
+    /// Must be called while holding the slot's lock, the generation only changes under a write
+    /// lock.
+    fn check(&self) -> Result<(), DanglingError> {
+        let actual = self.slot.gen.load(Ordering::Acquire);
+        if self.gen == actual {
+            Ok(())
+        } else {
+            Err(DanglingError::new(self.gen, actual))
+        }
+    }
+
+    /// Takes the value out, bumps the slot's generation and returns the slot to the recycled
+    /// pool.
+    ///
+    /// Does nothing if the allocation was already recycled.
+    /// Slots that run out of generations are retired instead.
+    fn recycle(self) {
+        let (value, gen) = {
+            let mut value = self
+                .slot
+                .value
+                .write()
+                .unwrap_or_else(PoisonError::into_inner);
+            if self.check().is_err() {
+                return;
+            }
+            let gen = self.gen + 1;
+            self.slot.gen.store(gen, Ordering::Release);
+            (value.take(), gen)
+        };
+        if gen != RETIRED_GEN {
+            RECYCLED_SYNC_ALLOCS
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .push(self.slot);
+        }
+        drop(value);
+    }
+}
+
+/// Leaked storage shared by every generation of a thread-safe allocation.
+#[derive(Default)]
+struct SyncSlot {
+    value: RwLock<Value>,
+    gen: AtomicU32,
+}
+
+impl SyncSlot {
+    fn leak() -> &'static Self {
+        Box::leak(Default::default())
+    }
+}
+
+static RECYCLED_SYNC_ALLOCS: Mutex<Vec<&'static SyncSlot>> = Mutex::new(Vec::new());
+
+#[test]
+fn read_from_other_threads() {
+    let mut span = SyncSpan::new();
+    let ptr = span.alloc(vec![1, 2, 3]);
+    std::thread::scope(|s| {
+        for _ in 0..4 {
+            s.spawn(|| assert_eq!(*ptr.read(), [1, 2, 3]));
+        }
+    });
+    std::thread::spawn(move || ptr.write().push(4))
+        .join()
+        .unwrap();
+    assert_eq!(*ptr.read(), [1, 2, 3, 4]);
+}
+
+#[test]
+fn stale_after_span_dropped_on_other_thread() {
+    let mut span = SyncSpan::new();
+    let ptr = span.alloc(5);
+    std::thread::spawn(move || drop(span)).join().unwrap();
+    assert!(!ptr.is_valid());
+    assert!(matches!(ptr.try_read(), Err(AccessError::Stale(_))));
+}
+
+#[test]
+fn sync_generation_overflow() {
+    let mut span = SyncSpan::new();
+    let ptr = span.alloc(5);
+    span.0[0].slot.gen.store(RETIRED_GEN - 1, Ordering::Release);
+    span.0[0].gen = RETIRED_GEN - 1;
+    drop(span);
+    assert!(!ptr.is_valid());
+    let slot = ptr.alloc.slot;
+    assert_eq!(slot.gen.load(Ordering::Acquire), RETIRED_GEN);
+    assert!(!RECYCLED_SYNC_ALLOCS
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|recycled| std::ptr::eq(*recycled, slot)));
+}
+
+#[test]
+fn sync_ptr_is_send_sync_copy() {
+    fn assert_send_sync_copy<T: Send + Sync + Copy>() {}
+    assert_send_sync_copy::<SyncPtr<String>>();
+}
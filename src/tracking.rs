@@ -0,0 +1,66 @@
+//! Origins of allocations, recorded to explain failed accesses.
+//!
+//! Requires the `debug-tracking` feature.
+
+use std::{
+    backtrace::{Backtrace, BacktraceStatus},
+    cell::RefCell,
+    collections::HashMap,
+    fmt,
+    panic::Location,
+};
+
+use crate::{Alloc, Slot};
+
+/// Where and by which [`Span`](crate::Span) an allocation was made.
+pub(crate) struct Origin {
+    location: &'static Location<'static>,
+    span: Option<&'static str>,
+    /// Only captured if enabled by `RUST_BACKTRACE`, see [`Backtrace::capture`].
+    backtrace: Backtrace,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "allocated at {}", self.location)?;
+        if let Some(span) = self.span {
+            write!(f, " in span '{span}'")?;
+        }
+        Ok(())
+    }
+}
+
+/// Records the caller as the origin of `alloc`, made by a span named `span`.
+///
+/// Origins are kept for every generation, so that stale pointers can still be explained.
+#[track_caller]
+pub(crate) fn record(alloc: Alloc, span: Option<&'static str>) {
+    let origin = Origin {
+        location: Location::caller(),
+        span,
+        backtrace: Backtrace::capture(),
+    };
+    ORIGINS.with(|origins| origins.borrow_mut().insert(alloc.key(), origin));
+}
+
+/// Describes the origin of `alloc` followed by `err`, and by the allocation's backtrace if one
+/// was captured.
+pub(crate) fn describe(alloc: Alloc, err: &dyn fmt::Display) -> Option<String> {
+    ORIGINS.with(|origins| {
+        let origins = origins.borrow();
+        let origin = origins.get(&alloc.key())?;
+        Some(match origin.backtrace.status() {
+            BacktraceStatus::Captured => {
+                format!(
+                    "{origin}: {err}\nallocation backtrace:\n{}",
+                    origin.backtrace
+                )
+            }
+            _ => format!("{origin}: {err}"),
+        })
+    })
+}
+
+thread_local! {
+    static ORIGINS: RefCell<HashMap<(*const Slot, u32), Origin>> = RefCell::new(HashMap::new());
+}
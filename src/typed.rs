@@ -0,0 +1,473 @@
+//! Homogeneous generational allocations.
+//!
+//! [`TypedSpan<T>`] and [`TypedPtr<T>`] mirror [`Span`](crate::Span) and [`Ptr<T>`](crate::Ptr),
+//! but every allocation stores a `T` directly instead of a `Box<dyn Any>`, so accessing it
+//! needs neither a heap indirection nor a downcast.
+//! Allocations are recycled through per-type pools.
+
+use std::{
+    any::{Any, TypeId},
+    cell::{Cell, Ref, RefCell, RefMut},
+    collections::HashMap,
+    hash::{Hash, Hasher},
+};
+
+use crate::{AccessError, DanglingError, RETIRED_GEN, STALE_DEFERRED, STALE_EMPTY, STALE_REUSED};
+
+/// Homogeneous generational allocations span.
+pub struct TypedSpan<T: 'static>(Vec<TypedAlloc<T>>);
+
+impl<T: 'static> Default for TypedSpan<T> {
+    fn default() -> Self {
+        Self(Vec::new())
+    }
+}
+
+impl<T: 'static> TypedSpan<T> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stores `v` in a generational allocation.
+    ///
+    /// Then the [`TypedSpan`] gets dropped it recycles it's generational allocations so that
+    /// they can be reused by other spans of the same type.
+    ///
+    /// The returned [`TypedPtr<T>`] is [`Copy`] even if the underlying `T` is not [`Copy`].
+    /// This pointer gets invalidated whenever it's [`TypedSpan`] is dropped.
+    #[must_use]
+    pub fn alloc(&mut self, v: T) -> TypedPtr<T> {
+        let (slot, released) = with_pool(|pool| match pool.recycled.pop() {
+            Some(slot) => (Some(slot), Vec::new()),
+            None => {
+                let released = pool.reclaim();
+                (pool.recycled.pop(), released)
+            }
+        });
+        drop(released);
+        let slot = slot.unwrap_or_else(TypedSlot::leak);
+        *slot.value.borrow_mut() = Some(v);
+        let alloc = TypedAlloc {
+            slot,
+            gen: slot.gen.get(),
+        };
+        self.0.push(alloc);
+        TypedPtr { alloc }
+    }
+
+    /// Returns the number of live allocations in this [`TypedSpan`].
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    /// Returns `true` if this [`TypedSpan`] holds no live allocations.
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    /// Recycles all allocations of this [`TypedSpan`] while keeping it around for reuse.
+    ///
+    /// All pointers allocated by this [`TypedSpan`] get invalidated, just as if it was dropped.
+    /// Slots that run out of generations are retired instead of being recycled.
+    /// Values that are still borrowed are dropped once released, and only then are their slots
+    /// recycled.
+    pub fn clear(&mut self) {
+        let mut values = Vec::with_capacity(self.0.len());
+        let mut recycled = Vec::with_capacity(self.0.len());
+        let mut borrowed = Vec::new();
+        for alloc in self.0.drain(..) {
+            if alloc.check().is_err() {
+                continue;
+            }
+            alloc.slot.gen.set(alloc.gen + 1);
+            match alloc.slot.value.try_borrow_mut() {
+                Ok(mut value) => values.push(value.take()),
+                Err(_) => {
+                    borrowed.push(alloc.slot);
+                    continue;
+                }
+            }
+            if alloc.slot.gen.get() != RETIRED_GEN {
+                recycled.push(alloc.slot);
+            }
+        }
+        let released = with_pool(|pool| {
+            pool.recycled.append(&mut recycled);
+            pool.borrowed.append(&mut borrowed);
+            pool.reclaim()
+        });
+        drop(values);
+        drop(released);
+    }
+
+    /// Moves all values out of this [`TypedSpan`] in allocation order, recycling their
+    /// allocations.
+    ///
+    /// All pointers allocated by this [`TypedSpan`] get invalidated. Values that are currently
+    /// borrowed are left to be dropped once released, see [`TypedSpan::clear`].
+    pub fn into_values(mut self) -> Vec<T> {
+        let values = self
+            .0
+            .iter()
+            .filter_map(|alloc| {
+                alloc.check().ok()?;
+                alloc.slot.value.try_borrow_mut().ok()?.take()
+            })
+            .collect();
+        self.clear();
+        values
+    }
+}
+
+impl<T: Clone + 'static> TypedSpan<T> {
+    /// Clones every value of this [`TypedSpan`] into a new, independent [`TypedSpan`], e.g. to
+    /// keep the original for a rollback.
+    ///
+    /// Also returns a map from the pointers of this [`TypedSpan`] to the pointers of their
+    /// clones. Pointers stored inside the values are cloned as they are and still point into
+    /// this [`TypedSpan`], the map is the tool to rewrite them.
+    ///
+    /// # Panics
+    /// Panics if a value is currently mutably borrowed.
+    #[allow(clippy::mutable_key_type)] // `Hash` only depends on the slot address and generation.
+    pub fn clone_span(&self) -> (TypedSpan<T>, HashMap<TypedPtr<T>, TypedPtr<T>>) {
+        let mut span = TypedSpan::new();
+        let remap = self
+            .0
+            .iter()
+            .map(|&alloc| {
+                let ptr = TypedPtr { alloc };
+                (ptr, span.alloc(ptr.read().clone()))
+            })
+            .collect();
+        (span, remap)
+    }
+}
+
+impl<T: 'static> Drop for TypedSpan<T> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
+
+/// Homogeneous generational pointer.
+///
+/// [`TypedPtr<T>`] is [`Copy`] even if the underlying `T` is not [`Copy`].
+pub struct TypedPtr<T: 'static> {
+    alloc: TypedAlloc<T>,
+}
+
+impl<T> Clone for TypedPtr<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for TypedPtr<T> {}
+
+/// Pointers are equal if they point to the same generation of the same allocation.
+impl<T> PartialEq for TypedPtr<T> {
+    fn eq(&self, other: &Self) -> bool {
+        std::ptr::eq(self.alloc.slot, other.alloc.slot) && self.alloc.gen == other.alloc.gen
+    }
+}
+
+impl<T> Eq for TypedPtr<T> {}
+
+impl<T> Hash for TypedPtr<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        std::ptr::hash(self.alloc.slot, state);
+        self.alloc.gen.hash(state);
+    }
+}
+
+impl<T: 'static> TypedPtr<T> {
+    /// Returns `true` if the [`TypedSpan`] owning the allocation is still alive.
+    ///
+    /// This never borrows the pointed-to value.
+    pub fn is_valid(&self) -> bool {
+        self.alloc.check().is_ok()
+    }
+
+    /// Returns the generation this pointer was created with.
+    pub fn generation(&self) -> u32 {
+        self.alloc.gen
+    }
+
+    /// Immutably borrows the pointed-to value.
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale or the value is currently mutably borrowed.
+    /// See [`TypedPtr::try_read`] for a non-panicking variant.
+    pub fn read(&self) -> Ref<'static, T> {
+        match self.try_read() {
+            Ok(borrow) => borrow,
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Mutably borrows the pointed-to value.
+    ///
+    /// # Panics
+    /// Panics if the pointer is stale or the value is currently borrowed.
+    /// See [`TypedPtr::try_write`] for a non-panicking variant.
+    pub fn write(&self) -> RefMut<'static, T> {
+        match self.try_write() {
+            Ok(borrow) => borrow,
+            Err(err) => self.fail(err),
+        }
+    }
+
+    /// Panics with `err` and the pointer's type, like [`Ptr::read`](crate::Ptr::read).
+    fn fail(&self, err: AccessError) -> ! {
+        let context = match err {
+            AccessError::Stale(_) => match self.alloc.slot.value.try_borrow() {
+                Ok(value) if value.is_none() => STALE_EMPTY,
+                Ok(_) if self.alloc.slot.is_deferred() => STALE_DEFERRED,
+                Ok(_) => STALE_REUSED,
+                Err(_) => "",
+            },
+            _ => "",
+        };
+        err.panic::<T>("TypedPtr", context)
+    }
+
+    /// Immutably borrows the pointed-to value, returning an error if the borrow is not possible.
+    pub fn try_read(&self) -> Result<Ref<'static, T>, AccessError> {
+        self.alloc.check()?;
+        let borrow = self
+            .alloc
+            .slot
+            .value
+            .try_borrow()
+            .map_err(|_| AccessError::AlreadyBorrowed)?;
+        Ok(Ref::map(borrow, |value| {
+            value.as_ref().expect("live allocation holds a value")
+        }))
+    }
+
+    /// Mutably borrows the pointed-to value, returning an error if the borrow is not possible.
+    pub fn try_write(&self) -> Result<RefMut<'static, T>, AccessError> {
+        self.alloc.check()?;
+        let borrow = self
+            .alloc
+            .slot
+            .value
+            .try_borrow_mut()
+            .map_err(|_| AccessError::AlreadyBorrowed)?;
+        Ok(RefMut::map(borrow, |value| {
+            value.as_mut().expect("live allocation holds a value")
+        }))
+    }
+}
+
+/// Homogeneous generational allocation.
+struct TypedAlloc<T: 'static> {
+    slot: &'static TypedSlot<T>,
+    gen: u32,
+}
+
+impl<T> Clone for TypedAlloc<T> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T> Copy for TypedAlloc<T> {}
+
+impl<T> TypedAlloc<T> {
+    fn check(&self) -> Result<(), DanglingError> {
+        let actual = self.slot.gen.get();
+        if self.gen == actual {
+            Ok(())
+        } else {
+            Err(DanglingError::new(self.gen, actual))
+        }
+    }
+}
+
+/// Leaked storage shared by every generation of a homogeneous allocation.
+struct TypedSlot<T> {
+    value: RefCell<Option<T>>,
+    gen: Cell<u32>,
+}
+
+impl<T: 'static> TypedSlot<T> {
+    fn leak() -> &'static Self {
+        Box::leak(Box::new(Self {
+            value: RefCell::new(None),
+            gen: Cell::new(0),
+        }))
+    }
+
+    /// Returns `true` if the slot was recycled while its value was borrowed, and still waits for
+    /// the borrow to be released, see [`TypedPool::reclaim`].
+    fn is_deferred(&self) -> bool {
+        RECYCLED_TYPED_ALLOCS.with(|pools| {
+            let Ok(pools) = pools.try_borrow() else {
+                return false;
+            };
+            pools
+                .get(&TypeId::of::<T>())
+                .and_then(|pool| pool.downcast_ref::<TypedPool<T>>())
+                .is_some_and(|pool| pool.borrowed.iter().any(|slot| std::ptr::eq(*slot, self)))
+        })
+    }
+}
+
+/// Recycled slots holding values of type `T`.
+struct TypedPool<T: 'static> {
+    recycled: Vec<&'static TypedSlot<T>>,
+    /// Recycled slots whose values were still borrowed, see [`TypedPool::reclaim`].
+    borrowed: Vec<&'static TypedSlot<T>>,
+}
+
+impl<T> TypedPool<T> {
+    /// Returns the recycled slots that are no longer borrowed to the pool.
+    ///
+    /// Their values are returned to be dropped once the pool is released, as dropping them may
+    /// allocate.
+    fn reclaim(&mut self) -> Vec<Option<T>> {
+        let mut values = Vec::new();
+        let recycled = &mut self.recycled;
+        self.borrowed.retain(|slot| {
+            let Ok(mut value) = slot.value.try_borrow_mut() else {
+                return true;
+            };
+            values.push(value.take());
+            if slot.gen.get() != RETIRED_GEN {
+                recycled.push(slot);
+            }
+            false
+        });
+        values
+    }
+}
+
+/// Runs `f` with the pool of slots holding values of type `T`.
+fn with_pool<T: 'static, R>(f: impl FnOnce(&mut TypedPool<T>) -> R) -> R {
+    RECYCLED_TYPED_ALLOCS.with(|pools| {
+        let mut pools = pools.borrow_mut();
+        let pool = pools.entry(TypeId::of::<T>()).or_insert_with(|| {
+            Box::new(TypedPool::<T> {
+                recycled: Vec::new(),
+                borrowed: Vec::new(),
+            })
+        });
+        f(pool.downcast_mut().expect("pools are keyed by their type"))
+    })
+}
+
+thread_local! {
+    static RECYCLED_TYPED_ALLOCS: RefCell<HashMap<TypeId, Box<dyn Any>>> =
+        RefCell::new(HashMap::new());
+}
+
+#[test]
+fn typed_span() {
+    let mut span = TypedSpan::new();
+    let ptr_1 = span.alloc("test".to_string());
+    let ptr_2 = ptr_1;
+    ptr_1.write().push('!');
+    assert_eq!(*ptr_2.read(), "test!");
+    assert_eq!(span.len(), 1);
+    drop(span);
+    assert!(!ptr_1.is_valid());
+    assert!(matches!(ptr_2.try_read(), Err(AccessError::Stale(_))));
+}
+
+#[test]
+fn typed_into_values() {
+    let mut span = TypedSpan::new();
+    let ptrs: Vec<_> = (0..3).map(|i| span.alloc(i.to_string())).collect();
+    assert_eq!(span.into_values(), ["0", "1", "2"]);
+    assert!(ptrs.iter().all(|ptr| !ptr.is_valid()));
+
+    let mut span = TypedSpan::new();
+    let reused = span.alloc("3".to_string());
+    assert_eq!(reused.generation(), 1);
+}
+
+#[test]
+fn typed_clone_span() {
+    let mut span = TypedSpan::new();
+    let ptrs: Vec<_> = (0..3).map(|i| span.alloc(vec![i])).collect();
+    let (clone, remap) = span.clone_span();
+    assert_eq!(clone.len(), 3);
+    for ptr in &ptrs {
+        let cloned = remap[ptr];
+        assert!(cloned != *ptr);
+        cloned.write().push(10);
+        assert_eq!(ptr.read().len(), 1);
+    }
+    drop(clone);
+    assert!(ptrs.iter().all(|ptr| ptr.is_valid()));
+    assert!(remap.values().all(|ptr| !ptr.is_valid()));
+}
+
+#[test]
+fn typed_span_recycles_per_type() {
+    let old = {
+        let mut span = TypedSpan::new();
+        span.alloc(5u32)
+    };
+    let mut other_span = TypedSpan::new();
+    let other = other_span.alloc(5u64);
+    assert_eq!(other.generation(), 0);
+
+    let mut span = TypedSpan::new();
+    let new = span.alloc(6u32);
+    assert_eq!(new.generation(), old.generation() + 1);
+    assert!(!old.is_valid());
+}
+
+#[test]
+fn typed_clear_borrowed() {
+    let mut span = TypedSpan::new();
+    let ptr = span.alloc(vec![5u8]);
+    let guard = ptr.read();
+    span.clear();
+    assert!(!ptr.is_valid());
+    assert_eq!(*guard, [5]);
+    let other = span.alloc(vec![6u8]);
+    assert!(!std::ptr::eq(other.alloc.slot, ptr.alloc.slot));
+    drop(guard);
+
+    span.clear();
+    let reused = span.alloc(vec![7u8]);
+    assert!(std::ptr::eq(reused.alloc.slot, ptr.alloc.slot));
+}
+
+#[test]
+fn typed_generation_overflow() {
+    let ptr = {
+        let mut span = TypedSpan::new();
+        span.alloc(5u16)
+    };
+    ptr.alloc.slot.gen.set(RETIRED_GEN - 1);
+    let mut span = TypedSpan::new();
+    let last = span.alloc(6u16);
+    assert!(std::ptr::eq(last.alloc.slot, ptr.alloc.slot));
+    drop(span);
+    assert!(!last.is_valid());
+
+    let mut span = TypedSpan::new();
+    let next = span.alloc(7u16);
+    assert!(!std::ptr::eq(next.alloc.slot, ptr.alloc.slot));
+    assert!(!ptr.is_valid());
+}
+
+#[test]
+#[should_panic(
+    expected = "TypedPtr<u8>: pointer is stale, expected generation 0 but the allocation is at \
+                generation 1 (the allocation was freed and reused for another value)"
+)]
+fn typed_read_stale_reused_panic() {
+    let ptr = {
+        let mut span = TypedSpan::new();
+        span.alloc(5u8)
+    };
+    let mut span = TypedSpan::new();
+    let _ = span.alloc(6u8);
+    let _ = *ptr.read();
+}
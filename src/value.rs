@@ -0,0 +1,275 @@
+//! Values of allocations, stored inline if they're small enough, see [`Value::new`].
+
+use alloc::{alloc::Layout, boxed::Box};
+use core::{
+    alloc::GlobalAlloc,
+    any::Any,
+    marker::PhantomData,
+    mem::{align_of, size_of, ManuallyDrop, MaybeUninit},
+    ptr::NonNull,
+};
+
+use crate::heap;
+#[cfg(doc)]
+use crate::PoolStorage;
+
+/// Value of an allocation.
+///
+/// Values are erased through [`Any`]: boxed ones by the box's vtable, and the others by a
+/// function turning their storage into a `*const dyn Any`, whose vtable holds the drop function
+/// and the [`TypeId`](core::any::TypeId). Keeping a raw pointer, the `TypeId` and a drop function
+/// next to the value instead would spare accesses their indirect calls, but grow every slot by the
+/// 16 bytes of the `TypeId`, and the public API hands values out as `&dyn Any` anyway, e.g.
+/// [`Span::for_each_any`](crate::Span::for_each_any).
+#[derive(Default)]
+pub(crate) enum Value {
+    #[default]
+    Empty,
+    Inline(Inline),
+    Boxed(Box<dyn Any>),
+    Allocated(Allocated),
+}
+
+impl Value {
+    /// Returns `true` if a `T` is stored inline instead of in a [`Box`].
+    pub(crate) const fn fits_inline<T>() -> bool {
+        size_of::<T>() <= size_of::<Storage>() && align_of::<T>() <= align_of::<Storage>()
+    }
+
+    /// Stores `v` inline if it fits, or in a new [`Box`] otherwise.
+    pub(crate) fn new<T: 'static>(v: T) -> Self {
+        if Self::fits_inline::<T>() {
+            Self::Inline(Inline::new(v))
+        } else {
+            Self::Boxed(Box::new(v))
+        }
+    }
+
+    /// Stores the value returned by `f` in memory of `allocator`, see
+    /// [`PoolStorage::with_allocator`].
+    ///
+    /// The memory is allocated before `f` runs, so that the value can be written straight into
+    /// it, and freed again if `f` panics.
+    pub(crate) fn allocated<T: 'static>(
+        f: impl FnOnce() -> T,
+        allocator: &'static dyn GlobalAlloc,
+    ) -> Self {
+        Self::Allocated(Allocated::new(f, allocator))
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        matches!(self, Self::Empty)
+    }
+
+    pub(crate) fn as_deref(&self) -> Option<&dyn Any> {
+        match self {
+            Self::Empty => None,
+            Self::Inline(inline) => Some(inline.get()),
+            Self::Boxed(boxed) => Some(&**boxed),
+            Self::Allocated(allocated) => Some(allocated.get()),
+        }
+    }
+
+    pub(crate) fn as_deref_mut(&mut self) -> Option<&mut dyn Any> {
+        match self {
+            Self::Empty => None,
+            Self::Inline(inline) => Some(inline.get_mut()),
+            Self::Boxed(boxed) => Some(&mut **boxed),
+            Self::Allocated(allocated) => Some(allocated.get_mut()),
+        }
+    }
+
+    /// Moves the value out, boxing it if it's stored inline or by a custom allocator.
+    pub(crate) fn into_box(self) -> Option<Box<dyn Any>> {
+        match self {
+            Self::Empty => None,
+            Self::Inline(inline) => Some(inline.into_box()),
+            Self::Boxed(boxed) => Some(boxed),
+            Self::Allocated(allocated) => Some(allocated.into_box()),
+        }
+    }
+
+    /// Moves the value out if it's a `T`.
+    pub(crate) fn downcast<T: 'static>(self) -> Result<T, Self> {
+        match self {
+            Self::Inline(inline) => inline.downcast().map_err(Self::Inline),
+            Self::Boxed(boxed) => boxed.downcast().map(|value| *value).map_err(Self::Boxed),
+            Self::Allocated(allocated) => allocated.downcast().map_err(Self::Allocated),
+            Self::Empty => Err(Self::Empty),
+        }
+    }
+}
+
+impl From<Box<dyn Any>> for Value {
+    fn from(boxed: Box<dyn Any>) -> Self {
+        Self::Boxed(boxed)
+    }
+}
+
+/// Inline storage of a [`Value`], large enough for e.g. a `String` or three `u64`s.
+#[repr(C, align(8))]
+struct Storage {
+    _bytes: [MaybeUninit<u8>; 24],
+}
+
+/// Value stored inline, see [`Value::fits_inline`].
+pub(crate) struct Inline {
+    storage: MaybeUninit<Storage>,
+    /// Turns a pointer to `storage` into a pointer to the stored value.
+    as_any: fn(*const u8) -> *const dyn Any,
+    /// Like boxed values, inline ones are neither [`Send`] nor [`Sync`].
+    _marker: PhantomData<Box<dyn Any>>,
+}
+
+impl Inline {
+    fn new<T: 'static>(v: T) -> Self {
+        debug_assert!(Value::fits_inline::<T>());
+        let mut storage = MaybeUninit::<Storage>::uninit();
+        // SAFETY: `T` fits into `Storage`, as checked by `Value::new`.
+        unsafe { storage.as_mut_ptr().cast::<T>().write(v) };
+        Self {
+            storage,
+            as_any: |ptr| ptr.cast::<T>(),
+            _marker: PhantomData,
+        }
+    }
+
+    fn get(&self) -> &dyn Any {
+        // SAFETY: `storage` holds the value `as_any` was made for.
+        unsafe { &*(self.as_any)(self.storage.as_ptr().cast()) }
+    }
+
+    fn get_mut(&mut self) -> &mut dyn Any {
+        // SAFETY: As above, and the pointer is derived from a mutable borrow.
+        unsafe { &mut *(self.as_any)(self.storage.as_mut_ptr().cast()).cast_mut() }
+    }
+
+    fn into_box(self) -> Box<dyn Any> {
+        let mut this = ManuallyDrop::new(self);
+        // SAFETY: `this` is never dropped.
+        unsafe { move_into_box(this.get_mut(), this.as_any) }
+    }
+
+    fn downcast<T: 'static>(self) -> Result<T, Self> {
+        if !self.get().is::<T>() {
+            return Err(self);
+        }
+        let this = ManuallyDrop::new(self);
+        // SAFETY: `storage` holds a `T`, which is moved out as `this` is never dropped.
+        Ok(unsafe { this.storage.as_ptr().cast::<T>().read() })
+    }
+}
+
+impl Drop for Inline {
+    fn drop(&mut self) {
+        // SAFETY: The value is dropped exactly once.
+        unsafe { core::ptr::drop_in_place(self.get_mut()) };
+    }
+}
+
+/// Value stored in memory of a custom allocator, see [`Value::allocated`].
+pub(crate) struct Allocated {
+    ptr: NonNull<u8>,
+    /// Turns `ptr` into a pointer to the stored value.
+    as_any: fn(*const u8) -> *const dyn Any,
+    allocator: &'static dyn GlobalAlloc,
+}
+
+impl Allocated {
+    fn new<T: 'static>(f: impl FnOnce() -> T, allocator: &'static dyn GlobalAlloc) -> Self {
+        let ptr = heap::alloc(Some(allocator), Layout::new::<T>());
+        let free = Free {
+            allocator,
+            ptr,
+            layout: Layout::new::<T>(),
+        };
+        // SAFETY: The memory was allocated for a `T`, and is freed by `free` if `f` panics.
+        unsafe { ptr.cast::<T>().as_ptr().write(f()) };
+        core::mem::forget(free);
+        Self {
+            ptr,
+            as_any: |ptr| ptr.cast::<T>(),
+            allocator,
+        }
+    }
+
+    fn get(&self) -> &dyn Any {
+        // SAFETY: `ptr` points to the value `as_any` was made for.
+        unsafe { &*(self.as_any)(self.ptr.as_ptr()) }
+    }
+
+    fn get_mut(&mut self) -> &mut dyn Any {
+        // SAFETY: As above, and the value is only reachable through `self`.
+        unsafe { &mut *(self.as_any)(self.ptr.as_ptr()).cast_mut() }
+    }
+
+    /// Frees the memory once the value was dropped or moved out.
+    fn free_on_drop(&self) -> Free {
+        Free {
+            allocator: self.allocator,
+            ptr: self.ptr,
+            layout: Layout::for_value(self.get()),
+        }
+    }
+
+    fn into_box(self) -> Box<dyn Any> {
+        let mut this = ManuallyDrop::new(self);
+        let _free = this.free_on_drop();
+        // SAFETY: `this` is never dropped, its memory is freed by `_free`.
+        unsafe { move_into_box(this.get_mut(), this.as_any) }
+    }
+
+    fn downcast<T: 'static>(self) -> Result<T, Self> {
+        if !self.get().is::<T>() {
+            return Err(self);
+        }
+        let this = ManuallyDrop::new(self);
+        let _free = this.free_on_drop();
+        // SAFETY: `ptr` points to a `T`, which is moved out as `this` is never dropped.
+        Ok(unsafe { this.ptr.cast::<T>().as_ptr().read() })
+    }
+}
+
+impl Drop for Allocated {
+    fn drop(&mut self) {
+        let _free = self.free_on_drop();
+        // SAFETY: The value is dropped exactly once, its memory is freed by `_free` even if this
+        // panics.
+        unsafe { core::ptr::drop_in_place(self.get_mut()) };
+    }
+}
+
+/// Memory of an [`Allocated`] value, freed when dropped.
+struct Free {
+    allocator: &'static dyn GlobalAlloc,
+    ptr: NonNull<u8>,
+    layout: Layout,
+}
+
+impl Drop for Free {
+    fn drop(&mut self) {
+        // SAFETY: The memory was allocated by `Allocated::new` with this layout, and its value
+        // was dropped, moved out or never written.
+        unsafe { heap::dealloc(Some(self.allocator), self.ptr, self.layout) };
+    }
+}
+
+/// Moves `value` into a new [`Box`].
+///
+/// # Safety
+/// `value` must be valid and never be used or dropped afterwards, and `as_any` must turn a
+/// pointer to the moved value into a pointer to it again.
+unsafe fn move_into_box(
+    value: *mut dyn Any,
+    as_any: fn(*const u8) -> *const dyn Any,
+) -> Box<dyn Any> {
+    // SAFETY: Guaranteed by the caller.
+    let layout = Layout::for_value(unsafe { &*value });
+    let ptr = heap::alloc(None, layout);
+    // SAFETY: The value is moved into global memory with its layout, which `Box` frees, and as
+    // the caller guarantees, it's not used at its old place anymore.
+    unsafe {
+        core::ptr::copy_nonoverlapping(value.cast::<u8>(), ptr.as_ptr(), layout.size());
+        Box::from_raw(as_any(ptr.as_ptr()).cast_mut())
+    }
+}